@@ -0,0 +1,55 @@
+// Collision detection benchmark: the spatial hash against the pairwise baseline.
+use std::time::{Duration, Instant};
+
+use crate::collision::{detect_collisions, detect_collisions_pairwise};
+use crate::scene::Scene;
+use crate::spawn::Spawner;
+
+// Run both detectors over a scene of `count` randomly placed particles and print
+// their timings. Build with `--release` for meaningful numbers.
+pub fn run(count: usize, seed: u64) -> Result<(), String> {
+    // About four cells per particle, so that some of them share cells.
+    let side = (((count * 4) as f64).sqrt().ceil() as u16).max(3);
+    // Half rockets, half fuel cells, so that shared cells hold pairs the matrix collides.
+    let mut spawner = Spawner::new(seed);
+    let scene = Scene::new(
+        (0..count)
+            .map(|i| match i % 2 {
+                0 => spawner.spawn_rocket(side, side),
+                _ => spawner.spawn_fuel_cell(side, side),
+            })
+            .collect(),
+    );
+
+    let (hashed, hashed_time) = time(|| detect_collisions(&scene));
+    let (pairwise, pairwise_time) = time(|| detect_collisions_pairwise(&scene));
+    if hashed != pairwise {
+        return Err(String::from(
+            "Spatial hash and pairwise detection disagree on the collisions found",
+        ));
+    }
+
+    println!(
+        "{} particles on a {}x{} playfield (seed {}), {} collisions:",
+        count,
+        side,
+        side,
+        seed,
+        hashed.len()
+    );
+    println!("  spatial hash: {:>10.3} ms", hashed_time.as_secs_f64() * 1000.0);
+    println!("  pairwise:     {:>10.3} ms", pairwise_time.as_secs_f64() * 1000.0);
+    Ok(())
+}
+
+// Best of a few runs, to smooth over one-off hiccups.
+fn time<T, F: Fn() -> T>(f: F) -> (T, Duration) {
+    let mut best = Duration::MAX;
+    let mut result = f();
+    for _ in 0..5 {
+        let start = Instant::now();
+        result = f();
+        best = best.min(start.elapsed());
+    }
+    (result, best)
+}
@@ -0,0 +1,147 @@
+// What happens to particles that reach the edge of the playfield.
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::collision::div_round;
+use crate::particle::{Particle, ParticleType};
+use crate::spatial::{Coordinate, SUBPIXEL_SCALE};
+use crate::sprite::Extent;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BoundaryMode {
+    // Bounce back in, keeping `restitution` percent of the speed across the edge.
+    Reflect { restitution: u16 },
+    // Leave through one edge and come back in through the opposite one.
+    Wrap,
+    // Stop at the edge, losing the speed across it.
+    Clamp,
+    // Leave the playfield for good.
+    Despawn,
+}
+
+impl Default for BoundaryMode {
+    fn default() -> Self {
+        BoundaryMode::Reflect { restitution: 100 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+// The boundary mode of every particle type: one for the whole scene, overridden per type.
+#[derive(Clone, Debug, Default)]
+pub struct Boundaries {
+    pub default: BoundaryMode,
+    pub per_type: HashMap<ParticleType, BoundaryMode>,
+}
+
+impl Boundaries {
+    pub fn mode_for(&self, kind: ParticleType) -> BoundaryMode {
+        self.per_type.get(&kind).copied().unwrap_or(self.default)
+    }
+}
+
+// Hashed in a fixed order, unlike the map's own, so that equal boundaries hash the same.
+impl Hash for Boundaries {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.default.hash(state);
+        let mut per_type: Vec<_> = self.per_type.iter().collect();
+        per_type.sort_by_key(|(kind, _)| **kind);
+        per_type.hash(state);
+    }
+}
+
+// Keep a particle that has just moved within the interior of a playfield of the given
+// size (in cells), returning the edges it crossed. A position is inside when it rounds
+// to an interior cell, the same test rendering and collisions use, on all four edges;
+// for particles covering several cells, `extent` keeps the whole sprite inside where
+// the playfield is big enough for it.
+// A despawning particle is left where it is; removing it is up to the caller.
+pub fn apply(
+    particle: &mut Particle,
+    mode: BoundaryMode,
+    extent: Extent,
+    cell_width: u16,
+    cell_height: u16,
+) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    let (min_dx, min_dy, max_dx, max_dy) = extent;
+    let x_range = shrink(interior_range(cell_width), min_dx, max_dx);
+    let y_range = shrink(interior_range(cell_height), min_dy, max_dy);
+    let before = particle.position;
+    if let Some(edge) = apply_axis(&mut particle.position.x, &mut particle.velocity.x, x_range, mode) {
+        edges.push(if edge { Edge::Right } else { Edge::Left });
+    }
+    if let Some(edge) = apply_axis(&mut particle.position.y, &mut particle.velocity.y, y_range, mode) {
+        edges.push(if edge { Edge::Bottom } else { Edge::Top });
+    }
+    // A wrapped particle jumped across the playfield without travelling; move the start
+    // of its path along with it so that collisions are not swept across the whole field.
+    if mode == BoundaryMode::Wrap {
+        particle.prev_position += Coordinate::new(
+            particle.position.x - before.x,
+            particle.position.y - before.y,
+        );
+    }
+    edges
+}
+
+// Move a particle lying outside the interior back to the nearest position inside it, by
+// the same test as `apply`; for when the playfield shrinks under it. Its velocity is
+// left alone, and being moved is not travel, so its path starts afresh.
+pub fn clamp_inside(particle: &mut Particle, extent: Extent, cell_width: u16, cell_height: u16) {
+    let (min_dx, min_dy, max_dx, max_dy) = extent;
+    let (x_low, x_high) = shrink(interior_range(cell_width), min_dx, max_dx);
+    let (y_low, y_high) = shrink(interior_range(cell_height), min_dy, max_dy);
+    particle.position.x = particle.position.x.clamp(x_low, x_high - 1);
+    particle.position.y = particle.position.y.clamp(y_low, y_high - 1);
+    particle.prev_position = particle.position;
+}
+
+// The subpixel positions, from inclusive to exclusive, that round to interior cells.
+fn interior_range(cells: u16) -> (i32, i32) {
+    let half = SUBPIXEL_SCALE / 2;
+    (SUBPIXEL_SCALE - half, (cells as i32 - 2).max(1) * SUBPIXEL_SCALE + half)
+}
+
+// Narrow a range of positions so that offsets from min to max cells stay within it;
+// left as it is if that would leave no room at all.
+fn shrink((low, high): (i32, i32), min: i32, max: i32) -> (i32, i32) {
+    let (low2, high2) = (low - min * SUBPIXEL_SCALE, high - max * SUBPIXEL_SCALE);
+    if low2 < high2 { (low2, high2) } else { (low, high) }
+}
+
+// Handle one axis; returns None if the position is inside, or whether it crossed the
+// far edge (true) or the near one (false).
+fn apply_axis(position: &mut i32, velocity: &mut i32, (low, high): (i32, i32), mode: BoundaryMode) -> Option<bool> {
+    let far = if *position >= high {
+        true
+    } else if *position < low {
+        false
+    } else {
+        return None;
+    };
+    match mode {
+        BoundaryMode::Reflect { restitution } => {
+            let overshoot = if far { *position - (high - 1) } else { low - *position };
+            let back = div_round(overshoot as i64 * restitution as i64, 100) as i32;
+            *position = if far { high - 1 - back } else { low + back };
+            *position = (*position).clamp(low, high - 1);
+            *velocity = -div_round(*velocity as i64 * restitution as i64, 100) as i32;
+        }
+        BoundaryMode::Wrap => {
+            *position = low + (*position - low).rem_euclid(high - low);
+        }
+        BoundaryMode::Clamp => {
+            *position = (*position).clamp(low, high - 1);
+            *velocity = 0;
+        }
+        BoundaryMode::Despawn => {}
+    }
+    Some(far)
+}
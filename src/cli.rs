@@ -0,0 +1,121 @@
+// Command-line options.
+use crate::game_loop::DEFAULT_TICK_RATE;
+use crate::spawn::Population;
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    // Simulation steps per second.
+    pub tick_rate: u32,
+    // Run without a terminal and print a summary instead.
+    pub headless: bool,
+    // Number of steps to simulate in headless mode.
+    pub ticks: u64,
+    // RNG seed for spawning; random when not given.
+    pub seed: Option<u64>,
+    // Playfield size in cells for headless mode.
+    pub width: u16,
+    pub height: u16,
+    // What to spawn at the start besides the rocket and its fuel cell.
+    pub population: Population,
+    // Write every step's boosts to this replay file.
+    pub record: Option<String>,
+    // Play back this replay file instead of reading input.
+    pub replay: Option<String>,
+    // Benchmark collision detection on this many particles and exit.
+    pub bench_collisions: Option<usize>,
+    // Config file to load instead of the default one.
+    pub config: Option<String>,
+    // Sprite file to load over the built-in sprites.
+    pub sprites: Option<String>,
+    // Print usage and exit.
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tick_rate: DEFAULT_TICK_RATE,
+            headless: false,
+            ticks: 1000,
+            seed: None,
+            width: 80,
+            height: 24,
+            population: Population::default(),
+            record: None,
+            replay: None,
+            config: None,
+            sprites: None,
+            bench_collisions: None,
+            help: false,
+        }
+    }
+}
+
+pub const USAGE: &str = "\
+Usage: book [OPTIONS]
+
+Options:
+  --tick-rate <HZ>   Simulation steps per second (default 40)
+  --headless         Run without a terminal and print a summary
+  --ticks <N>        Steps to simulate in headless mode (default 1000)
+  --seed <SEED>      RNG seed for spawning, to reproduce a run (default random)
+  --width <CELLS>    Playfield width in headless mode (default 80)
+  --height <CELLS>   Playfield height in headless mode (default 24)
+  --debris <N>       Pieces of debris to spawn (default 0)
+  --depots <N>       Fuel depots to spawn (default 0)
+  --motherships <N>  Motherships to spawn (default 0)
+  --record <FILE>    Record the session to a replay file
+  --replay <FILE>    Play back a replay file (its seed and size take precedence)
+  --config <FILE>    Config file to load (default book.toml, if present)
+  --sprites <FILE>   Sprite file to load over the built-in sprites
+  --bench-collisions <N>
+                     Benchmark collision detection on N particles and exit
+  -h, --help         Print this help";
+
+impl Options {
+    // Parse the arguments following the program name. Returns the message to print on error.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--tick-rate" => {
+                    options.tick_rate = parse_value(&arg, args.next())?;
+                    if options.tick_rate == 0 {
+                        return Err(String::from("--tick-rate must be greater than zero"));
+                    }
+                }
+                "--headless" => options.headless = true,
+                "--ticks" => options.ticks = parse_value(&arg, args.next())?,
+                "--seed" => options.seed = Some(parse_value(&arg, args.next())?),
+                "--width" => options.width = parse_value(&arg, args.next())?,
+                "--height" => options.height = parse_value(&arg, args.next())?,
+                "--debris" => options.population.debris = parse_value(&arg, args.next())?,
+                "--depots" => options.population.depots = parse_value(&arg, args.next())?,
+                "--motherships" => {
+                    options.population.motherships = parse_value(&arg, args.next())?
+                }
+                "--record" => options.record = Some(parse_value(&arg, args.next())?),
+                "--replay" => options.replay = Some(parse_value(&arg, args.next())?),
+                "--config" => options.config = Some(parse_value(&arg, args.next())?),
+                "--sprites" => options.sprites = Some(parse_value(&arg, args.next())?),
+                "--bench-collisions" => {
+                    options.bench_collisions = Some(parse_value(&arg, args.next())?)
+                }
+                "-h" | "--help" => options.help = true,
+                _ => return Err(format!("Unknown argument: {}\n\n{}", arg, USAGE)),
+            }
+        }
+        if options.width < 3 || options.height < 3 {
+            return Err(String::from("--width and --height must be at least 3"));
+        }
+        Ok(options)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
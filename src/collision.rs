@@ -1,10 +1,541 @@
-use crate::particle::ParticleId;
+// Collision detection, run as its own simulation phase after particles move.
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
-#[derive(Clone, Debug)]
+use crate::particle::{Particle, ParticleId, ParticleType};
+use crate::scene::Scene;
+use crate::spatial::{ConsoleCell, Coordinate, SUBPIXEL_SCALE};
+use crate::spatial_hash::SpatialHash;
+use crate::sprite::Extent;
+
+// What happens when two particle types overlap.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionKind {
+    Refuel,
+    Impact,
+    Pickup,
+    Ignore,
+}
+
+// Every collision is between two particles, in the console cell where they overlap.
+// `relative_velocity` is the first participant's velocity minus the second's.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Collision {
-    // A refuel-type collision occurring at a specific console cell
-    // with the list of participating particle IDs (stable across storage changes).
+    // A rocket touching a fuel cell.
     Refuel {
-        participants: Vec<ParticleId>,
+        rocket: ParticleId,
+        fuel_cell: ParticleId,
+        cell: ConsoleCell,
+        relative_velocity: Coordinate,
+    },
+    // Two particles running into each other; neither has a special role.
+    Impact {
+        a: ParticleId,
+        b: ParticleId,
+        cell: ConsoleCell,
+        relative_velocity: Coordinate,
     },
+    // A particle collecting another, which is used up.
+    Pickup {
+        collector: ParticleId,
+        item: ParticleId,
+        cell: ConsoleCell,
+        relative_velocity: Coordinate,
+    },
+}
+
+impl Collision {
+    // The two particles involved, in their roles' order.
+    pub fn participants(&self) -> (ParticleId, ParticleId) {
+        match *self {
+            Collision::Refuel { rocket, fuel_cell, .. } => (rocket, fuel_cell),
+            Collision::Impact { a, b, .. } => (a, b),
+            Collision::Pickup { collector, item, .. } => (collector, item),
+        }
+    }
+}
+
+// Says, for each pair of particle types, whether they collide and how. Asymmetric kinds
+// give the first type of the pair the first role (the rocket of a refuel, the collector
+// of a pickup). Pairs that were never set are ignored.
+// Pairs can also be given a restitution, in percent: their impacts then bounce the two
+// particles off each other, keeping that share of their closing speed (100 is perfectly
+// elastic). Pairs without one pass through each other as before.
+#[derive(Clone, Debug)]
+pub struct CollisionMatrix {
+    kinds: HashMap<(ParticleType, ParticleType), CollisionKind>,
+    // Stored under both orders of the pair.
+    restitution: HashMap<(ParticleType, ParticleType), u16>,
+}
+
+impl Default for CollisionMatrix {
+    fn default() -> Self {
+        let mut matrix = Self {
+            kinds: HashMap::new(),
+            restitution: HashMap::new(),
+        };
+        matrix.set(ParticleType::Rocket, ParticleType::FuelCell, CollisionKind::Refuel);
+        matrix.set(ParticleType::Rocket, ParticleType::Rocket, CollisionKind::Impact);
+        matrix.set(ParticleType::FuelCell, ParticleType::FuelCell, CollisionKind::Ignore);
+        matrix.set(ParticleType::Rocket, ParticleType::Debris, CollisionKind::Impact);
+        matrix.set_restitution(ParticleType::Rocket, ParticleType::Debris, Some(80));
+        matrix.set(ParticleType::Debris, ParticleType::Debris, CollisionKind::Impact);
+        matrix.set_restitution(ParticleType::Debris, ParticleType::Debris, Some(100));
+        matrix
+    }
+}
+
+impl CollisionMatrix {
+    // Set the kind for a pair, replacing whatever was set for it in either order.
+    pub fn set(&mut self, first: ParticleType, second: ParticleType, kind: CollisionKind) {
+        self.kinds.remove(&(second, first));
+        self.kinds.insert((first, second), kind);
+    }
+
+    // Make impacts between a pair bounce with the given restitution, or stop them bouncing.
+    pub fn set_restitution(&mut self, a: ParticleType, b: ParticleType, restitution: Option<u16>) {
+        match restitution {
+            Some(r) => {
+                self.restitution.insert((a, b), r);
+                self.restitution.insert((b, a), r);
+            }
+            None => {
+                self.restitution.remove(&(a, b));
+                self.restitution.remove(&(b, a));
+            }
+        }
+    }
+
+    pub fn restitution(&self, a: ParticleType, b: ParticleType) -> Option<u16> {
+        self.restitution.get(&(a, b)).copied()
+    }
+
+    // The kind for a pair, and whether the pair had to be swapped to match the roles.
+    pub fn get(&self, first: ParticleType, second: ParticleType) -> (CollisionKind, bool) {
+        if let Some(kind) = self.kinds.get(&(first, second)) {
+            (*kind, false)
+        } else if let Some(kind) = self.kinds.get(&(second, first)) {
+            (*kind, true)
+        } else {
+            (CollisionKind::Ignore, false)
+        }
+    }
+}
+
+// Hashed in a fixed order, unlike the maps' own, so that equal matrices hash the same.
+impl Hash for CollisionMatrix {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut kinds: Vec<_> = self.kinds.iter().collect();
+        kinds.sort_by_key(|(pair, _)| **pair);
+        kinds.hash(state);
+        let mut restitution: Vec<_> = self.restitution.iter().collect();
+        restitution.sort_by_key(|(pair, _)| **pair);
+        restitution.hash(state);
+    }
+}
+
+// How close two particles must come on both axes, in subpixels, to collide: half a
+// cell, so a particle cannot pass through another's cell without touching it.
+const CONTACT_DISTANCE: i32 = SUBPIXEL_SCALE / 2;
+
+// Collisions between particles that touched during the last step, pair by pair.
+// Each particle is swept along its path from `prev_position` to `position`, so fast
+// particles collide with whatever they passed through, not just where they ended up,
+// and particles with multi-cell sprites collide on every cell of their footprint.
+pub fn detect_collisions(scene: &Scene) -> Vec<Collision> {
+    let particles = scene.particles();
+    let sprites = scene.sprites();
+    let hash = SpatialHash::build(particles.iter().map(|p| swept_cells(p, sprites.extent(sprites.id_for(p)))));
+    collide_pairs(scene, hash.candidate_pairs())
+}
+
+// The same collisions as `detect_collisions`, found by comparing every pair of particles.
+// Only kept as the baseline for the collision benchmark.
+pub fn detect_collisions_pairwise(scene: &Scene) -> Vec<Collision> {
+    let count = scene.particles().len();
+    collide_pairs(
+        scene,
+        (0..count).flat_map(|i| (i + 1..count).map(move |j| (i, j))),
+    )
+}
+
+// The collisions among candidate pairs of particle indices, as the matrix sees them.
+fn collide_pairs<I: IntoIterator<Item = (usize, usize)>>(scene: &Scene, pairs: I) -> Vec<Collision> {
+    let particles = scene.particles();
+    let sprites = scene.sprites();
+    let mut collisions = Vec::new();
+    for (i, j) in pairs {
+        let (p, q) = (&particles[i], &particles[j]);
+        let (p_footprint, q_footprint) = (
+            sprites.footprint(sprites.id_for(p)),
+            sprites.footprint(sprites.id_for(q)),
+        );
+        if let Some(cell) = contact_cell(p, q, p_footprint, q_footprint)
+            && let Some(collision) = classify(scene.collision_matrix(), p, q, cell)
+        {
+            collisions.push(collision);
+        }
+    }
+    collisions
+}
+
+// The cells a particle's path covered during the last step with its whole footprint,
+// widened by half the contact distance so that any two particles that came into
+// contact share at least one cell.
+fn swept_cells(p: &Particle, extent: Extent) -> impl Iterator<Item = ConsoleCell> + use<> {
+    let reach = CONTACT_DISTANCE / 2;
+    let (min_dx, min_dy, max_dx, max_dy) = extent;
+    let (from, to) = (p.prev_position, p.position);
+    let xs = cell_index(from.x.min(to.x) - reach, min_dx)..=cell_index(from.x.max(to.x) + reach, max_dx);
+    let ys = cell_index(from.y.min(to.y) - reach, min_dy)..=cell_index(from.y.max(to.y) + reach, max_dy);
+    ys.flat_map(move |y| xs.clone().map(move |x| ConsoleCell::new(x, y)))
+}
+
+// The cell index a subpixel coordinate rounds to, as `Coordinate::to_cell` does, moved
+// by `offset` cells.
+fn cell_index(v: i32, offset: i32) -> u16 {
+    ((v + SUBPIXEL_SCALE / 2).div_euclid(SUBPIXEL_SCALE) + offset).max(0) as u16
+}
+
+// A straight line from the start of the step to its end.
+type Path = (Coordinate, Coordinate);
+
+// One cell of a particle's footprint, `offset` cells away from the particle, moving
+// along with it.
+fn path(p: &Particle, (dx, dy): (i32, i32)) -> Path {
+    let shift = Coordinate::new(dx * SUBPIXEL_SCALE, dy * SUBPIXEL_SCALE);
+    (
+        Coordinate::new(p.prev_position.x + shift.x, p.prev_position.y + shift.y),
+        Coordinate::new(p.position.x + shift.x, p.position.y + shift.y),
+    )
+}
+
+// Where two particles touched during the last step, if they did: a cell both their
+// footprints ended up covering, or else the first contact along the way between any
+// cell of one footprint and any cell of the other, see `path_contact`.
+fn contact_cell(p: &Particle, q: &Particle, p_cells: &[(i32, i32)], q_cells: &[(i32, i32)]) -> Option<ConsoleCell> {
+    for &p_offset in p_cells {
+        for &q_offset in q_cells {
+            let end = path(p, p_offset).1.to_cell();
+            if end == path(q, q_offset).1.to_cell() {
+                return Some(end);
+            }
+        }
+    }
+    for &p_offset in p_cells {
+        for &q_offset in q_cells {
+            if let Some(cell) = path_contact(path(p, p_offset), path(q, q_offset)) {
+                return Some(cell);
+            }
+        }
+    }
+    None
+}
+
+// The cell between two moving cells when their paths first came within
+// `CONTACT_DISTANCE` of each other, if they did.
+fn path_contact(p: Path, q: Path) -> Option<ConsoleCell> {
+    let t = first_contact(p, q)?;
+    let (p_at, q_at) = (position_at(p, t), position_at(q, t));
+    Some(Coordinate::new((p_at.x + q_at.x) / 2, (p_at.y + q_at.y) / 2).to_cell())
+}
+
+// When during the step two moving cells first came within `CONTACT_DISTANCE` of each
+// other, if they did; the start of the step if they already were.
+fn first_contact(p: Path, q: Path) -> Option<StepTime> {
+    // Offset of q from p at the start of the step, and how it changed over the step.
+    let start = Coordinate::new(q.0.x - p.0.x, q.0.y - p.0.y);
+    let change = Coordinate::new(
+        (q.1.x - q.0.x) - (p.1.x - p.0.x),
+        (q.1.y - q.0.y) - (p.1.y - p.0.y),
+    );
+    let (x_from, x_to) = contact_interval(start.x, change.x)?;
+    let (y_from, y_to) = contact_interval(start.y, change.y)?;
+    let from = latest(latest((0, 1), x_from), y_from);
+    let to = earliest(earliest((1, 1), x_to), y_to);
+    is_before(from, to).then_some(from)
+}
+
+// Where along a path a cell was at time `t` of the step.
+fn position_at(path: Path, t: StepTime) -> Coordinate {
+    let at = |a: i32, b: i32| a + ((b - a) as i64 * t.0 / t.1) as i32;
+    Coordinate::new(at(path.0.x, path.1.x), at(path.0.y, path.1.y))
+}
+
+// A time within the step as a fraction (numerator, positive denominator), 0 being the
+// start of the step and 1 its end.
+type StepTime = (i64, i64);
+
+// When, along one axis, an offset starting at `start` and changing by `change` over
+// the step is within `CONTACT_DISTANCE` of zero. The interval is open and may extend
+// beyond the step; None if the offset never gets that close.
+fn contact_interval(start: i32, change: i32) -> Option<(StepTime, StepTime)> {
+    let reach = CONTACT_DISTANCE as i64;
+    let (start, change) = (start as i64, change as i64);
+    if change == 0 {
+        return (start.abs() < reach).then_some(((i64::MIN / 4, 1), (i64::MAX / 4, 1)));
+    }
+    // |start + t·change| is unchanged by flipping both signs, so make the change positive.
+    let (start, change) = if change < 0 { (-start, -change) } else { (start, change) };
+    Some(((-reach - start, change), (reach - start, change)))
+}
+
+fn is_before(a: StepTime, b: StepTime) -> bool {
+    (a.0 as i128) * (b.1 as i128) < (b.0 as i128) * (a.1 as i128)
+}
+
+fn latest(a: StepTime, b: StepTime) -> StepTime {
+    if is_before(a, b) { b } else { a }
+}
+
+fn earliest(a: StepTime, b: StepTime) -> StepTime {
+    if is_before(a, b) { a } else { b }
+}
+
+// Build the collision for an overlapping pair, or None if the matrix ignores the pair.
+pub fn classify(
+    matrix: &CollisionMatrix,
+    p: &Particle,
+    q: &Particle,
+    cell: ConsoleCell,
+) -> Option<Collision> {
+    let (kind, swapped) = matrix.get(p.kind, q.kind);
+    let (first, second) = if swapped { (q, p) } else { (p, q) };
+    let relative_velocity = Coordinate::new(
+        first.velocity.x - second.velocity.x,
+        first.velocity.y - second.velocity.y,
+    );
+    match kind {
+        CollisionKind::Refuel => Some(Collision::Refuel {
+            rocket: first.uid,
+            fuel_cell: second.uid,
+            cell,
+            relative_velocity,
+        }),
+        CollisionKind::Impact => Some(Collision::Impact {
+            a: first.uid,
+            b: second.uid,
+            cell,
+            relative_velocity,
+        }),
+        CollisionKind::Pickup => Some(Collision::Pickup {
+            collector: first.uid,
+            item: second.uid,
+            cell,
+            relative_velocity,
+        }),
+        CollisionKind::Ignore => None,
+    }
+}
+
+// How two colliding particles bounce apart, see `bounce_impulses`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounce {
+    // Changes in momentum, mass times subpixels per frame, of the first and the second.
+    pub impulses: (Coordinate, Coordinate),
+    // Where the two first touched, to put them back to if they went on to pass each other
+    // during the step; their new velocities would otherwise carry them back through.
+    pub rewind: Option<(Coordinate, Coordinate)>,
+}
+
+// The impulses that bounce two colliding particles apart, or None if they are already
+// separating or neither can be pushed. The bounce is along the axis on which they were
+// further apart when they first touched, which for cell-sized particles is the face they
+// met on, so that particles which passed through each other within one step still
+// bounce; `restitution` is in percent.
+// Momentum is conserved up to integer rounding: the impulses are equal and opposite,
+// and a particle without mass acts as an immovable wall.
+pub fn bounce_impulses(p: &Particle, q: &Particle, restitution: u16) -> Option<Bounce> {
+    // Particles touching on other cells of their sprites than their own are taken as
+    // they ended up.
+    let (p_path, q_path) = (path(p, (0, 0)), path(q, (0, 0)));
+    let t = first_contact(p_path, q_path).unwrap_or((1, 1));
+    let (p_at, q_at) = (position_at(p_path, t), position_at(q_path, t));
+    let offset = Coordinate::new(q_at.x - p_at.x, q_at.y - p_at.y);
+    let end_offset = Coordinate::new(q.position.x - p.position.x, q.position.y - p.position.y);
+    let closing = Coordinate::new(p.velocity.x - q.velocity.x, p.velocity.y - q.velocity.y);
+    let along_x = if offset.x.abs() != offset.y.abs() {
+        offset.x.abs() > offset.y.abs()
+    } else {
+        closing.x.abs() >= closing.y.abs()
+    };
+    let (offset, end_offset, closing) = if along_x {
+        (offset.x, end_offset.x, closing.x)
+    } else {
+        (offset.y, end_offset.y, closing.y)
+    };
+    // Moving apart already, or not moving relative to each other at all.
+    if closing == 0 || (offset != 0 && (offset > 0) != (closing > 0)) {
+        return None;
+    }
+
+    let factor = (100 + restitution as i64) * closing as i64;
+    let impulse = match (p.mass, q.mass) {
+        (Some(mp), Some(mq)) => {
+            let (mp, mq) = (mp as i64, mq as i64);
+            div_round(factor * mp * mq, 100 * (mp + mq))
+        }
+        (Some(m), None) | (None, Some(m)) => div_round(factor * m as i64, 100),
+        (None, None) => return None,
+    } as i32;
+    let on_q = if along_x {
+        Coordinate::new(impulse, 0)
+    } else {
+        Coordinate::new(0, impulse)
+    };
+    let passed = offset != 0 && end_offset.signum() != offset.signum();
+    Some(Bounce {
+        impulses: (Coordinate::new(-on_q.x, -on_q.y), on_q),
+        rewind: passed.then_some((p_at, q_at)),
+    })
+}
+
+// Integer division rounding to the nearest whole number, halves away from zero, so
+// that repeated bounces do not steadily bleed speed the way truncation would.
+pub fn div_round(n: i64, d: i64) -> i64 {
+    (2 * n + d * n.signum()) / (2 * d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::{Boundaries, BoundaryMode};
+    use crate::game_events::GameEvent;
+
+    // A particle that moved from one cell to another during the last step.
+    fn moved(kind: ParticleType, from: (i32, i32), to: (i32, i32)) -> Particle {
+        let mut p = Particle::new(
+            Some(Coordinate::new(to.0 * SUBPIXEL_SCALE, to.1 * SUBPIXEL_SCALE)),
+            None,
+            None,
+            kind,
+            Coordinate::new(200, 200),
+        );
+        p.prev_position = Coordinate::new(from.0 * SUBPIXEL_SCALE, from.1 * SUBPIXEL_SCALE);
+        p
+    }
+
+    #[test]
+    fn fast_rocket_refuels_from_a_fuel_cell_it_passed_through() {
+        let scene = Scene::new(vec![
+            moved(ParticleType::Rocket, (5, 5), (9, 5)),
+            moved(ParticleType::FuelCell, (7, 5), (7, 5)),
+        ]);
+        let collisions = detect_collisions(&scene);
+        assert!(matches!(
+            collisions.as_slice(),
+            [Collision::Refuel { cell, .. }] if *cell == ConsoleCell::new(7, 5)
+        ));
+        assert_eq!(collisions, detect_collisions_pairwise(&scene));
+    }
+
+    #[test]
+    fn parallel_paths_a_cell_apart_do_not_touch() {
+        let scene = Scene::new(vec![
+            moved(ParticleType::Rocket, (2, 5), (6, 5)),
+            moved(ParticleType::FuelCell, (2, 6), (6, 6)),
+        ]);
+        assert!(detect_collisions(&scene).is_empty());
+        assert!(detect_collisions_pairwise(&scene).is_empty());
+    }
+
+    #[test]
+    fn wrapping_is_not_swept_across_the_playfield() {
+        let mut rocket = moved(ParticleType::Rocket, (1, 5), (1, 5));
+        rocket.velocity = Coordinate::new(-SUBPIXEL_SCALE * 2, 0);
+        let mut scene = Scene::new(vec![rocket, moved(ParticleType::FuelCell, (10, 5), (10, 5))]);
+        scene.set_boundaries(Boundaries {
+            default: BoundaryMode::Wrap,
+            ..Boundaries::default()
+        });
+        let events = scene.step(20, 10, &[]);
+        assert!(scene.particles()[0].position.x > 10 * SUBPIXEL_SCALE);
+        assert!(!events.iter().any(|e| matches!(e, GameEvent::Refuel { .. })));
+        assert!(detect_collisions(&scene).is_empty());
+    }
+
+    fn debris(x: i32, velocity: i32, mass: Option<u16>) -> Particle {
+        let mut p = Particle::new(
+            Some(Coordinate::new(x, 10 * SUBPIXEL_SCALE)),
+            Some(Coordinate::new(velocity, 0)),
+            None,
+            ParticleType::Debris,
+            Coordinate::new(200, 200),
+        );
+        p.mass = mass;
+        p
+    }
+
+    // Step two pieces of debris into each other, bouncing with `restitution`, and
+    // return their velocities along x afterwards.
+    fn collide(p: Particle, q: Particle, restitution: u16) -> (i32, i32) {
+        let mut matrix = CollisionMatrix::default();
+        matrix.set_restitution(ParticleType::Debris, ParticleType::Debris, Some(restitution));
+        let mut scene = Scene::new(vec![p, q]);
+        scene.set_collision_matrix(matrix);
+        scene.step(40, 20, &[]);
+        let after = scene.particles();
+        (after[0].velocity.x, after[1].velocity.x)
+    }
+
+    #[test]
+    fn impulses_conserve_momentum() {
+        let (mp, mq) = (10, 20);
+        let (vp, vq) = collide(debris(640, 40, Some(mp)), debris(680, -10, Some(mq)), 80);
+        let change = mp as i32 * (vp - 40) + mq as i32 * (vq + 10);
+        // Each velocity is rounded to the nearest subpixel per frame.
+        assert!(change.abs() <= (mp + mq) as i32 / 2, "momentum changed by {}", change);
+        assert!(vp < vq, "still approaching: {} and {}", vp, vq);
+    }
+
+    #[test]
+    fn full_restitution_is_elastic() {
+        // Equal masses swap velocities.
+        assert_eq!(collide(debris(640, 40, Some(10)), debris(680, -10, Some(10)), 100), (-10, 40));
+    }
+
+    #[test]
+    fn zero_restitution_is_perfectly_inelastic() {
+        let (vp, vq) = collide(debris(640, 40, Some(10)), debris(680, -10, Some(20)), 0);
+        assert_eq!(vp, vq);
+        // The common velocity keeps the momentum, (10·40 - 20·10) / 30.
+        assert_eq!(vp, 7);
+    }
+
+    #[test]
+    fn massless_particles_are_walls() {
+        assert_eq!(collide(debris(640, 40, Some(10)), debris(680, 0, None), 100), (-40, 0));
+    }
+
+    #[test]
+    fn particles_passing_each_other_within_a_step_bounce() {
+        // The first crosses the second, 60 subpixels away, in one step of 100.
+        let mut matrix = CollisionMatrix::default();
+        matrix.set_restitution(ParticleType::Debris, ParticleType::Debris, Some(100));
+        let mut scene = Scene::new(vec![debris(640, 200, Some(10)), debris(700, 0, Some(10))]);
+        scene.set_collision_matrix(matrix);
+        scene.step(40, 20, &[]);
+        let after = scene.particles();
+        assert_eq!((after[0].velocity.x, after[1].velocity.x), (0, 200));
+        // Put back to where they touched rather than left on the far side.
+        assert_eq!((after[0].position.x, after[1].position.x), (668, 700));
+
+        // So they carry on apart instead of running back into each other.
+        for _ in 0..3 {
+            let events = scene.step(40, 20, &[]);
+            assert!(events.is_empty(), "{:?}", events);
+        }
+        let after = scene.particles();
+        assert_eq!((after[0].velocity.x, after[1].velocity.x), (0, 200));
+        assert!(after[0].position.x < after[1].position.x);
+    }
+
+    #[test]
+    fn separating_particles_do_not_bounce() {
+        let p = debris(640, -10, Some(10));
+        let q = debris(680, 10, Some(10));
+        assert_eq!(bounce_impulses(&p, &q, 100), None);
+    }
 }
@@ -0,0 +1,63 @@
+// Deferred scene mutations. Game rules queue commands while a step is running and the
+// scene applies them all together at the end of the step, so nothing a rule does can
+// change what the other rules see during that step.
+use crate::emitter::Emitter;
+use crate::particle::{Particle, ParticleId};
+use crate::spatial::Coordinate;
+
+#[derive(Clone, Debug)]
+pub enum SceneCommand {
+    Spawn(Particle),
+    Despawn(ParticleId),
+    SetFuel(ParticleId, u16),
+    SetVelocity(ParticleId, Coordinate),
+    // Moves the particle; its path through the step, for collisions, still starts where
+    // it was before the step.
+    SetPosition(ParticleId, Coordinate),
+    // Changes the particle's momentum: adds the impulse divided by its mass to its
+    // velocity. Particles without mass are not affected.
+    ApplyImpulse(ParticleId, Coordinate),
+    // Sets off a burst emitter at a position; purely cosmetic.
+    Burst(Coordinate, Emitter),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SceneCommands {
+    queue: Vec<SceneCommand>,
+}
+
+impl SceneCommands {
+    pub fn spawn(&mut self, particle: Particle) {
+        self.queue.push(SceneCommand::Spawn(particle));
+    }
+
+    pub fn despawn(&mut self, id: ParticleId) {
+        self.queue.push(SceneCommand::Despawn(id));
+    }
+
+    pub fn set_fuel(&mut self, id: ParticleId, fuel: u16) {
+        self.queue.push(SceneCommand::SetFuel(id, fuel));
+    }
+
+    #[allow(dead_code)]
+    pub fn set_velocity(&mut self, id: ParticleId, velocity: Coordinate) {
+        self.queue.push(SceneCommand::SetVelocity(id, velocity));
+    }
+
+    pub fn set_position(&mut self, id: ParticleId, position: Coordinate) {
+        self.queue.push(SceneCommand::SetPosition(id, position));
+    }
+
+    pub fn apply_impulse(&mut self, id: ParticleId, impulse: Coordinate) {
+        self.queue.push(SceneCommand::ApplyImpulse(id, impulse));
+    }
+
+    pub fn burst(&mut self, position: Coordinate, emitter: Emitter) {
+        self.queue.push(SceneCommand::Burst(position, emitter));
+    }
+
+    // Take every queued command, leaving the buffer empty.
+    pub fn take(&mut self) -> Vec<SceneCommand> {
+        std::mem::take(&mut self.queue)
+    }
+}
@@ -0,0 +1,319 @@
+// Config file: optional TOML settings that override the built-in defaults.
+//
+//   [keys]
+//   thrust_up = ["Up", "w", "k"]
+//   brake = ["b", "Space"]
+//
+//   [input]
+//   mode = "auto"            # or "key_release" / "repeat_timeout"
+//   repeat_delay_ms = 750
+//   repeat_timeout_ms = 100
+//
+//   [[collisions]]
+//   a = "rocket"
+//   b = "fuel_cell"
+//   kind = "pickup"          # or "refuel" / "impact" / "ignore"
+//   restitution = 80         # optional, in percent: impacts bounce the pair apart
+//
+//   [boundary]
+//   mode = "reflect"         # or "wrap" / "clamp" / "despawn"
+//   restitution = 100        # reflect only, in percent of the speed kept
+//   types.debris = { mode = "wrap" }
+//
+//   [player]
+//   control = "heading"      # or "axes": how the player's rocket is steered
+//
+//   [[fields]]
+//   kind = "point"           # or "uniform" (x, y) / "drag" (per_mille)
+//   x = 40                   # in cells
+//   y = 12
+//   strength = 600           # negative to repel
+//
+// Actions missing from `[keys]` keep their default keys, which the actions listed cannot
+// take from them; Quit must be left with at least one key. Pairs of particle types
+// missing from `[[collisions]]` keep their default kind, and particle types missing
+// from `[boundary]` use its scene-wide mode.
+use crossterm::event::KeyCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::boundary::{Boundaries, BoundaryMode};
+use crate::collision::{CollisionKind, CollisionMatrix};
+use crate::force::ForceField;
+use crate::input::{DEFAULT_REPEAT_DELAY, DEFAULT_REPEAT_TIMEOUT, InputModeSetting};
+use crate::keymap::{Action, KeyBindings, parse_key};
+use crate::particle::{ControlModel, ParticleType};
+use crate::spatial::{Coordinate, SUBPIXEL_SCALE};
+
+// Read from the working directory when no `--config` is given, if it exists.
+pub const DEFAULT_CONFIG_PATH: &str = "book.toml";
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub keys: KeyBindings,
+    pub input_mode: InputModeSetting,
+    // How long after its press, and after its last repeat once it repeats, a key counts
+    // as released in the fallback input mode.
+    pub repeat_delay: Duration,
+    pub repeat_timeout: Duration,
+    pub collisions: CollisionMatrix,
+    pub boundaries: Boundaries,
+    pub fields: Vec<ForceField>,
+    pub player_control: ControlModel,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keys: KeyBindings::default(),
+            input_mode: InputModeSetting::Auto,
+            repeat_delay: DEFAULT_REPEAT_DELAY,
+            repeat_timeout: DEFAULT_REPEAT_TIMEOUT,
+            collisions: CollisionMatrix::default(),
+            boundaries: Boundaries::default(),
+            fields: Vec::new(),
+            player_control: ControlModel::Axes,
+        }
+    }
+}
+
+// The file as written; validated and converted into a `Config`.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    keys: HashMap<Action, Vec<String>>,
+    #[serde(default)]
+    input: InputSection,
+    #[serde(default)]
+    collisions: Vec<CollisionEntry>,
+    #[serde(default)]
+    boundary: BoundarySection,
+    #[serde(default)]
+    fields: Vec<FieldEntry>,
+    #[serde(default)]
+    player: PlayerSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct PlayerSection {
+    #[serde(default)]
+    control: ControlModel,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct InputSection {
+    #[serde(default)]
+    mode: InputModeSetting,
+    repeat_delay_ms: Option<u64>,
+    repeat_timeout_ms: Option<u64>,
+}
+
+// One pair of particle types; `a` takes the first role of asymmetric kinds.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CollisionEntry {
+    a: ParticleType,
+    b: ParticleType,
+    kind: CollisionKind,
+    restitution: Option<u16>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct BoundarySection {
+    mode: Option<BoundaryModeName>,
+    restitution: Option<u16>,
+    #[serde(default)]
+    types: HashMap<ParticleType, BoundaryEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoundaryEntry {
+    mode: BoundaryModeName,
+    restitution: Option<u16>,
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum BoundaryModeName {
+    Reflect,
+    Wrap,
+    Clamp,
+    Despawn,
+}
+
+// A force field as written: point positions are in cells, accelerations in
+// 1/FORCE_SCALE subpixels per step per step.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+enum FieldEntry {
+    Uniform { x: i32, y: i32 },
+    Point { x: u16, y: u16, strength: i32 },
+    Drag { per_mille: u16 },
+}
+
+impl From<FieldEntry> for ForceField {
+    fn from(entry: FieldEntry) -> Self {
+        match entry {
+            FieldEntry::Uniform { x, y } => ForceField::Uniform {
+                acceleration: Coordinate::new(x, y),
+            },
+            FieldEntry::Point { x, y, strength } => ForceField::Point {
+                position: Coordinate::new(x as i32 * SUBPIXEL_SCALE, y as i32 * SUBPIXEL_SCALE),
+                strength,
+            },
+            FieldEntry::Drag { per_mille } => ForceField::Drag { per_mille },
+        }
+    }
+}
+
+// Combine a boundary mode with its restitution, which only reflecting takes.
+fn boundary_mode(name: BoundaryModeName, restitution: Option<u16>) -> Result<BoundaryMode, String> {
+    match (name, restitution) {
+        (BoundaryModeName::Reflect, r) => {
+            let restitution = r.unwrap_or(100);
+            if restitution > 100 {
+                return Err(String::from("restitution must be at most 100"));
+            }
+            Ok(BoundaryMode::Reflect { restitution })
+        }
+        (_, Some(_)) => Err(String::from("restitution only applies to the reflect boundary mode")),
+        (BoundaryModeName::Wrap, None) => Ok(BoundaryMode::Wrap),
+        (BoundaryModeName::Clamp, None) => Ok(BoundaryMode::Clamp),
+        (BoundaryModeName::Despawn, None) => Ok(BoundaryMode::Despawn),
+    }
+}
+
+impl Config {
+    // Load the config at `path`, or the default config file if there is one.
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        match path {
+            Some(path) => Self::from_file(Path::new(path)),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        let mut config = Self::default();
+        // Checked against the bindings as they end up, so that a key is not quietly taken
+        // from an action the file leaves at its default keys.
+        let mut claimed: HashMap<KeyCode, Action> = HashMap::new();
+        let mut bound = Vec::new();
+        for (&action, names) in file.keys.iter() {
+            let mut keys = Vec::new();
+            for name in names.iter() {
+                let key = parse_key(name).ok_or_else(|| format!("unknown key \"{}\"", name))?;
+                let default = config.keys.action_for(key).filter(|a| !file.keys.contains_key(a));
+                if let Some(other) = claimed.insert(key, action).or(default)
+                    && other != action
+                {
+                    return Err(format!(
+                        "key \"{}\" is bound to both {:?} and {:?}",
+                        name, other, action
+                    ));
+                }
+                keys.push(key);
+            }
+            bound.push((action, keys));
+        }
+        for (action, keys) in bound {
+            config.keys.bind(action, &keys);
+        }
+        // Raw mode turns off Ctrl-C, so without a Quit key there would be no way out.
+        if !config.keys.is_bound(Action::Quit) {
+            return Err(String::from("no key is bound to Quit"));
+        }
+
+        config.input_mode = file.input.mode;
+        if let Some(ms) = file.input.repeat_delay_ms {
+            config.repeat_delay = Duration::from_millis(ms);
+        }
+        if let Some(ms) = file.input.repeat_timeout_ms {
+            config.repeat_timeout = Duration::from_millis(ms);
+        }
+        for entry in file.collisions {
+            if entry.restitution.is_some_and(|r| r > 100) {
+                return Err(String::from("restitution must be at most 100"));
+            }
+            config.collisions.set(entry.a, entry.b, entry.kind);
+            config.collisions.set_restitution(entry.a, entry.b, entry.restitution);
+        }
+
+        let boundary = file.boundary;
+        if boundary.mode.is_some() || boundary.restitution.is_some() {
+            let mode = boundary.mode.unwrap_or(BoundaryModeName::Reflect);
+            config.boundaries.default = boundary_mode(mode, boundary.restitution)?;
+        }
+        for (kind, entry) in boundary.types {
+            config
+                .boundaries
+                .per_type
+                .insert(kind, boundary_mode(entry.mode, entry.restitution)?);
+        }
+
+        config.fields = file.fields.into_iter().map(ForceField::from).collect();
+        config.player_control = file.player.control;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_keys_moves_them_between_actions() {
+        let config = Config::parse("[keys]\nbrake = [\"Space\", \"x\"]\nquit = [\"Esc\"]\n").unwrap();
+        assert_eq!(config.keys.action_for(KeyCode::Char(' ')), Some(Action::Brake));
+        assert_eq!(config.keys.action_for(KeyCode::Char('x')), Some(Action::Brake));
+        assert_eq!(config.keys.action_for(KeyCode::Char('b')), None);
+        assert_eq!(config.keys.action_for(KeyCode::Esc), Some(Action::Quit));
+        // Actions the file leaves out keep their default keys.
+        assert_eq!(config.keys.action_for(KeyCode::Up), Some(Action::ThrustUp));
+
+        // A default key is free once its own action is given other keys.
+        let config = Config::parse("[keys]\nbrake = [\"q\"]\nquit = [\"Esc\"]\n").unwrap();
+        assert_eq!(config.keys.action_for(KeyCode::Char('q')), Some(Action::Brake));
+    }
+
+    #[test]
+    fn default_keys_are_not_taken_from_actions_left_alone() {
+        let error = Config::parse("[keys]\nbrake = [\"q\"]\n").unwrap_err();
+        assert!(error.contains("bound to both"), "{}", error);
+    }
+
+    #[test]
+    fn quit_must_keep_a_key() {
+        let error = Config::parse("[keys]\nquit = []\n").unwrap_err();
+        assert_eq!(error, "no key is bound to Quit");
+    }
+
+    #[test]
+    fn unknown_and_duplicate_keys_are_rejected() {
+        let error = Config::parse("[keys]\nbrake = [\"Hyper\"]\n").unwrap_err();
+        assert_eq!(error, "unknown key \"Hyper\"");
+
+        let error = Config::parse("[keys]\nbrake = [\"x\"]\npause = [\"x\"]\n").unwrap_err();
+        assert!(error.contains("key \"x\" is bound to both"), "{}", error);
+
+        // Naming a key twice for the same action is harmless.
+        assert!(Config::parse("[keys]\nbrake = [\"x\", \"x\"]\n").is_ok());
+    }
+}
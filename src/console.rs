@@ -1,45 +1,62 @@
-use crossterm::QueueableCommand;
-use crossterm::cursor::{Hide, MoveTo, MoveToColumn, MoveToRow};
-use crossterm::terminal::{Clear, ClearType};
-use std::io::{Write, stdout};
-use crossterm::style::{Color, SetBackgroundColor, SetForegroundColor};
-use crossterm::style::{Colors, SetColors};
+use crossterm::style::Color;
 
 use crate::border::BorderChars;
-use crate::particle::{Particle, Boost, ParticleType, ParticleId};
+use crate::frame::FrameBuffer;
+use crate::input::InputMode;
+use crate::particle::{Particle, Boost, ParticleId};
+use crate::render::{RenderBackend, DEFAULT_COLORS};
+use crate::boundary::Boundaries;
+use crate::collision::CollisionMatrix;
+use crate::emitter::Emitter;
+use crate::force::ForceField;
+use crate::sprite::SpriteRegistry;
 use crate::scene::Scene;
+use crate::spatial::ConsoleCell;
 use crate::spatial::SUBPIXEL_SCALE;
-use crate::collision::Collision;
-use crate::game_events::GameEvent; // add: returning events
+use crate::game_events::GameEvent;
 
 pub const DEFAULT_FOREGROUND_COLOR: Color = Color::White;
 pub const DEFAULT_BACKGROUND_COLOR: Color = Color::Black;
+// Width in characters of the information pane drawn to the right of the playfield.
+pub const INFO_PANE_WIDTH: u16 = 32;
 
-pub struct Console {
+// Readouts shown in the information pane besides the tracked particle's state.
+pub struct InfoPane<'a> {
+    pub pressed_keys: &'a str,
+    // Simulation steps per second.
+    pub tick_rate: u32,
+    // Seed of the RNG that spawned the scene, for reproducing the run.
+    pub seed: u64,
+    // How held keys are detected, which depends on what the terminal reports.
+    pub input_mode: InputMode,
+}
+
+pub struct Console<B: RenderBackend> {
     pub(crate) cell_width: u16,
     pub(crate) cell_height: u16,
     pub(crate) height: i32,
     pub(crate) width: i32,
     scene: Scene,
+    frame: FrameBuffer,
+    backend: B,
 }
 
-impl Console {
-    pub fn new(cell_width: u16, cell_height: u16) -> Self {
+impl<B: RenderBackend> Console<B> {
+    pub fn new(cell_width: u16, cell_height: u16, backend: B) -> Self {
         Self {
             cell_width,
             cell_height,
             width: cell_width as i32 * SUBPIXEL_SCALE,
             height: cell_height as i32 * SUBPIXEL_SCALE,
             scene: Scene::new(vec![]),
+            frame: FrameBuffer::new(cell_width + 1 + INFO_PANE_WIDTH, cell_height),
+            backend,
         }
     }
 
-    // Find the index of a particle by its stable UID.
-    pub fn find_particle_index_by_id(&self, id: ParticleId) -> Option<usize> {
-        self.scene
-            .particles
-            .iter()
-            .position(|q| q.uid == id)
+    #[allow(dead_code)]
+    pub fn backend(&self) -> &B {
+        &self.backend
     }
 
     fn get_border_char(row: u16, col: u16, height: u16, width: u16) -> Option<BorderChars> {
@@ -62,207 +79,230 @@ impl Console {
         }
     }
 
-    pub fn draw_borders(&self) -> &Self {
-        let mut stdout = stdout();
-        stdout.queue(Clear(ClearType::All)).unwrap();
+    pub fn draw_borders(&mut self) -> &Self {
+        self.frame.clear_screen(&mut self.backend);
 
         for console_j in 0..self.cell_height {
-            stdout.queue(MoveToRow(console_j)).unwrap();
-
             for console_i in 0..self.cell_width {
-                stdout.queue(MoveToColumn(console_i)).unwrap();
-
                 if let Some(border_char) =
                     Self::get_border_char(console_j, console_i, self.cell_height, self.cell_width)
                 {
-                    stdout.write(border_char.to_string().as_bytes()).unwrap();
+                    self.frame.put_str(
+                        ConsoleCell::new(console_i, console_j),
+                        &border_char.to_string(),
+                        DEFAULT_COLORS,
+                    );
                 }
             }
         }
-        stdout.flush().unwrap();
+        self.frame.present(&mut self.backend);
         self
     }
 
-    pub fn add_particle(&mut self, particle: Particle) {
-        self.scene.add_particle(particle);
+    // Adapt to a new playfield size: recompute the bounds, keep every particle inside
+    // the new interior, and redraw everything from a cleared screen.
+    pub fn resize(&mut self, cell_width: u16, cell_height: u16) {
+        self.cell_width = cell_width;
+        self.cell_height = cell_height;
+        self.width = cell_width as i32 * SUBPIXEL_SCALE;
+        self.height = cell_height as i32 * SUBPIXEL_SCALE;
+
+        let frame_width = cell_width + 1 + INFO_PANE_WIDTH;
+        self.frame = FrameBuffer::new(frame_width, cell_height);
+        self.backend.resize(frame_width, cell_height);
+        self.scene.clamp_into(cell_width, cell_height);
+        self.draw_borders();
     }
 
-    pub fn get_particle(&self, index: usize) -> Option<&Particle> {
-        self.scene.particles.get(index)
+    pub fn add_particle(&mut self, particle: Particle) -> ParticleId {
+        self.scene.add_particle(particle)
     }
 
-    // Runs a simulation tick:
-    // 1) capture current renderables and erase them;
-    // 2) update existing particles in the scene (in-place) with provided boosts;
-    // 3) draw the new frame.
-    // The boosts vector is applied in scene order; missing entries default to None.
-    pub fn tick(&mut self, boosts: Vec<Option<Boost>>) -> Vec<GameEvent> {
-        let mut stdout = stdout();
-        stdout.queue(Hide).unwrap();
-
-        // 1) Erase previously drawn cells by overwriting them with spaces
-        let prev_particles = self.scene.get_renderable(self.cell_width, self.cell_height).cells;
-        for (cell, _ch, _color) in prev_particles {
-            if cell.x >= 1
-                && cell.x < self.cell_width - 1
-                && cell.y >= 1
-                && cell.y < self.cell_height - 1
-            {
-                stdout.queue(MoveTo(cell.x, cell.y)).unwrap();
-                stdout.queue(SetColors(Colors::new(
-                    DEFAULT_FOREGROUND_COLOR,
-                    DEFAULT_BACKGROUND_COLOR,
-                ))).unwrap();
-                stdout.write(" ".as_bytes()).unwrap();
-            }
-        }
+    pub fn set_collision_matrix(&mut self, matrix: CollisionMatrix) {
+        self.scene.set_collision_matrix(matrix);
+    }
 
-        // 2) Update particles in-place
-        let count = self.scene.particles.len();
-        let bounds = (self.width, self.height, self.cell_width, self.cell_height);
-        for i in 0..count {
-            let b = boosts.get(i).cloned().unwrap_or(None);
-            self.scene.particles[i].update(bounds, b);
-        }
+    pub fn set_boundaries(&mut self, boundaries: Boundaries) {
+        self.scene.set_boundaries(boundaries);
+    }
+
+    pub fn set_sprites(&mut self, sprites: SpriteRegistry) {
+        self.scene.set_sprites(sprites);
+    }
 
-        // 3) Build renderable for the new frame (cells + collisions)
-        let renderable_now = self.scene.get_renderable(self.cell_width, self.cell_height);
+    pub fn add_field(&mut self, field: ForceField) {
+        self.scene.add_field(field);
+    }
+
+    pub fn attach_emitter(&mut self, host: ParticleId, emitter: Emitter) {
+        self.scene.attach_emitter(host, emitter);
+    }
 
-        // 3a) Draw current scene cells
-        for (cell, ch, color) in renderable_now.cells {
+    pub fn get_particle(&self, id: ParticleId) -> Option<&Particle> {
+        self.scene.get(id)
+    }
+
+    // Runs one simulation step on the scene; nothing is drawn, see `render`.
+    // Boosts are keyed by the id of the particle they are for, see `Scene::step`.
+    pub fn step(&mut self, boosts: &[(ParticleId, Boost)]) -> Vec<GameEvent> {
+        self.scene.step(self.cell_width, self.cell_height, boosts)
+    }
+
+    // Draw the current scene into the back buffer and present only what changed,
+    // together with anything written to the information pane since the last render.
+    pub fn render(&mut self) {
+        let renderable = self.scene.get_renderable(self.cell_width, self.cell_height);
+
+        // Redraw the playfield interior from scratch in the back buffer
+        self.frame.clear_rect(
+            ConsoleCell::new(1, 1),
+            ConsoleCell::new(self.cell_width - 1, self.cell_height - 1),
+        );
+        for (cell, ch, color) in renderable.cells {
             if cell.x >= 1
                 && cell.x < self.cell_width - 1
                 && cell.y >= 1
                 && cell.y < self.cell_height - 1
             {
-                stdout.queue(MoveTo(cell.x, cell.y)).unwrap();
-                stdout.queue(SetForegroundColor(color.foreground)).unwrap();
-                stdout.queue(SetBackgroundColor(color.background)).unwrap();
-                let s = ch.to_string();
-                stdout.write(s.as_bytes()).unwrap();
-                stdout.queue(SetForegroundColor(DEFAULT_FOREGROUND_COLOR)).unwrap();
-                stdout.queue(SetBackgroundColor(DEFAULT_BACKGROUND_COLOR)).unwrap();
-            }
-        }
-        stdout.flush().unwrap();
-
-        // 4) Produce events from collisions (per-collision refuel event)
-        let mut events: Vec<GameEvent> = Vec::new();
-        for coll in renderable_now.collisions {
-            match coll {
-                Collision::Refuel { participants, .. } => {
-                    // pick first rocket and first fuel cell in the group
-                    let mut rocket_idx: Option<usize> = None;
-                    let mut fuel_idx: Option<usize> = None;
-
-                    for pid in participants {
-                        // Resolve stable ParticleId to current scene index
-                        if let Some(idx) = self.find_particle_index_by_id(pid) {
-                            match self.scene.particles[idx].kind {
-                                ParticleType::Rocket if rocket_idx.is_none() => {
-                                    rocket_idx = Some(idx)
-                                }
-                                ParticleType::FuelCell if fuel_idx.is_none() => {
-                                    fuel_idx = Some(idx)
-                                }
-                                _ => {}
-                            }
-                            if rocket_idx.is_some() && fuel_idx.is_some() {
-                                break;
-                            }
-                        }
-                    }
-
-                    if let (Some(ri), Some(fi)) = (rocket_idx, fuel_idx) {
-                        events.push(GameEvent::Refuel {
-                            rocket_idx: ri,
-                            fuel_cell_idx: fi,
-                        });
-                    }
-                }
+                self.frame.set(cell, ch, color);
             }
         }
+        self.frame.present(&mut self.backend);
+    }
 
-        // Return generated events
-        events
+    // Write one line of the information pane, padded to the pane width so that
+    // shorter text overwrites whatever was there on the previous frame.
+    fn info_line(&mut self, row: u16, text: &str) {
+        let width = INFO_PANE_WIDTH as usize;
+        self.frame.put_str(
+            ConsoleCell::new(self.cell_width + 1, row),
+            &format!("{:<width$}", text),
+            DEFAULT_COLORS,
+        );
     }
 
-    pub fn display_info(&self, particle: &Particle, pressed_button_str: &str) {
-        let mut stdout = stdout();
-        stdout.queue(Hide).unwrap();
-            // Header
-            stdout.queue(MoveTo(self.cell_width + 1, 0)).unwrap();
-            stdout.write("Information.".as_bytes()).unwrap();
-
-            // Fuel bar: occupies exactly 32 characters in the margin
-            let margin_width: usize = 32;
-            let filled = (particle.fuel as usize * margin_width) / 510;
-            let bar = format!("{}{}", "#".repeat(filled), " ".repeat(margin_width - filled));
-            stdout.queue(MoveTo(self.cell_width + 1, 1)).unwrap();
-            stdout.queue(Clear(ClearType::UntilNewLine)).unwrap();
-            stdout.write(bar.as_bytes()).unwrap();
-
-            // Pressed keys
-            stdout.queue(MoveTo(self.cell_width + 1, 2)).unwrap();
-            stdout.queue(Clear(ClearType::UntilNewLine)).unwrap();
-            stdout.write(pressed_button_str.as_bytes()).unwrap();
-
-            // Position / Velocity / Acceleration readouts
-            stdout.queue(MoveTo(self.cell_width + 1, 4)).unwrap();
-            stdout
-                .write(
-                    format!(
-                        "P: {:04}i, {:04}j",
-                        particle.position.y, particle.position.x,
-                    )
-                    .as_bytes(),
-                )
-                .unwrap();
-            stdout.queue(MoveTo(self.cell_width + 1, 5)).unwrap();
-            stdout
-                .write(
-                    format!(
-                        "V: {:04}i, {:04}j",
-                        particle.velocity.y, particle.velocity.x,
-                    )
-                    .as_bytes(),
-                )
-                .unwrap();
-            stdout.queue(MoveTo(self.cell_width + 1, 6)).unwrap();
-            stdout
-                .write(
-                    format!(
-                        "A: {:04}i, {:04}j",
-                        particle.acceleration.y, particle.acceleration.x,
-                    )
-                    .as_bytes(),
-                )
-                .unwrap();
-
-        stdout.queue(MoveTo(self.cell_width + 1, 7)).unwrap();
-        stdout
-            .write(
-                format!(
-                    "F: {:03}",
-                    particle.fuel,
-                )
-                .as_bytes(),
-            )
-            .unwrap();
-        stdout.flush().unwrap();
+    // Fill in the information pane; it appears on screen with the next `render`.
+    pub fn display_info(&mut self, particle: &Particle, info: &InfoPane) {
+        // Header
+        self.info_line(0, "Information.");
+
+        // Fuel bar: occupies exactly INFO_PANE_WIDTH characters in the margin
+        let width = INFO_PANE_WIDTH as usize;
+        let filled = (particle.fuel as usize * width) / 510;
+        self.info_line(1, &"#".repeat(filled));
+
+        // Pressed keys
+        self.info_line(2, info.pressed_keys);
+
+        // Position / Velocity / Acceleration readouts
+        self.info_line(
+            4,
+            &format!("P: {:04}i, {:04}j", particle.position.y, particle.position.x),
+        );
+        self.info_line(
+            5,
+            &format!("V: {:04}i, {:04}j", particle.velocity.y, particle.velocity.x),
+        );
+        self.info_line(
+            6,
+            &format!("A: {:04}i, {:04}j", particle.acceleration.y, particle.acceleration.x),
+        );
+        self.info_line(7, &format!("F: {:03}", particle.fuel));
+
+        self.info_line(9, &format!("Tick rate: {} Hz", info.tick_rate));
+        self.info_line(10, &format!("Seed: {}", info.seed));
+        self.info_line(11, &format!("Input: {}", info.input_mode));
     }
 
-    // Set a particle's fuel to the provided amount (no-op if out of bounds).
-    pub fn set_particle_fuel(&mut self, idx: usize, amount: u16) {
-        if let Some(part) = self.scene.particles.get_mut(idx) {
+    // Set a particle's fuel to the provided amount (no-op if it is not in the scene).
+    #[allow(dead_code)]
+    pub fn set_particle_fuel(&mut self, id: ParticleId, amount: u16) {
+        if let Some(part) = self.scene.get_mut(id) {
             part.fuel = amount;
         }
     }
 
-    // Remove a particle at the given index (no-op if out of bounds).
-    pub fn remove_particle(&mut self, idx: usize) {
-        if idx < self.scene.particles.len() {
-            self.scene.particles.remove(idx);
-        }
+    // Remove a particle (no-op if it is not in the scene).
+    #[allow(dead_code)]
+    pub fn remove_particle(&mut self, id: ParticleId) {
+        self.scene.remove_particle(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle::ParticleType;
+    use crate::render::BufferBackend;
+    use crate::spatial::Coordinate;
+
+    const WIDTH: u16 = 12;
+    const HEIGHT: u16 = 6;
+
+    fn console() -> Console<BufferBackend> {
+        let backend = BufferBackend::new(WIDTH + 1 + INFO_PANE_WIDTH, HEIGHT);
+        let mut console = Console::new(WIDTH, HEIGHT, backend);
+        console.draw_borders();
+        console
+    }
+
+    fn debris_at(x: i32, y: i32, velocity: Coordinate) -> Particle {
+        Particle::new(
+            Some(Coordinate::new(x * SUBPIXEL_SCALE, y * SUBPIXEL_SCALE)),
+            Some(velocity),
+            None,
+            ParticleType::Debris,
+            Coordinate::new(200, 200),
+        )
+    }
+
+    fn playfield_row(console: &Console<BufferBackend>, y: u16) -> String {
+        console.backend().row(y).chars().take(WIDTH as usize).collect()
+    }
+
+    #[test]
+    fn draws_the_borders() {
+        let console = console();
+        assert_eq!(playfield_row(&console, 0), "┌──────────┐");
+        assert_eq!(playfield_row(&console, 2), "│          │");
+        assert_eq!(playfield_row(&console, HEIGHT - 1), "└──────────┘");
+    }
+
+    #[test]
+    fn places_sprites_by_their_anchor() {
+        let mut console = console();
+        let mut mothership = debris_at(5, 2, Coordinate::new(0, 0));
+        mothership.sprite = console.scene.sprites().id("mothership");
+        console.add_particle(mothership);
+        console.render();
+        // The anchor is the middle of the top row.
+        assert_eq!(playfield_row(&console, 2), "│   /=\\    │");
+        assert_eq!(playfield_row(&console, 3), "│   \\_/    │");
+        assert_eq!(console.backend().get(ConsoleCell::new(5, 2)).unwrap().1.foreground, Color::Cyan);
+    }
+
+    #[test]
+    fn unchanged_frames_write_nothing() {
+        let mut console = console();
+        console.add_particle(debris_at(3, 3, Coordinate::new(0, 0)));
+        console.render();
+        let writes = console.backend().writes();
+        console.step(&[]);
+        console.render();
+        assert_eq!(console.backend().writes(), writes);
+    }
+
+    #[test]
+    fn moved_particles_blank_their_old_cell() {
+        let mut console = console();
+        // Particles move half their velocity per step, see `Particle::update`.
+        console.add_particle(debris_at(3, 3, Coordinate::new(2 * SUBPIXEL_SCALE, 0)));
+        console.render();
+        assert_eq!(playfield_row(&console, 3), "│  #       │");
+        let writes = console.backend().writes();
+        console.step(&[]);
+        console.render();
+        assert_eq!(playfield_row(&console, 3), "│   #      │");
+        assert_eq!(console.backend().writes() - writes, 2);
     }
 }
@@ -0,0 +1,226 @@
+// Emitters of sparks: short-lived cosmetic particles such as exhaust and explosions.
+// Sparks live in a pool of their own, apart from the scene's particles, so they never
+// take part in collisions, rules or boundaries; they just fly, fade and disappear.
+use crossterm::style::Color;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::particle::{Particle, ParticleColors, ParticleId};
+use crate::spatial::{ConsoleCell, Coordinate};
+
+// Most sparks alive at once; emitters go quiet while the pool is full.
+const MAX_SPARKS: usize = 1024;
+
+// Sparks do not affect the game, but are still drawn from a fixed seed so that a replay
+// looks the same every time.
+const SPARK_SEED: u64 = 0x5eed;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EmitterMode {
+    // Sparks per 100 ticks, for as long as the emitter is attached.
+    Continuous { rate: u32 },
+    // This many sparks at once, after which the emitter is done.
+    Burst { count: u16 },
+}
+
+// The direction an emitter sends its sparks in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aim {
+    // Opposite the host's thrust, and only while it thrusts.
+    Exhaust,
+    // Any direction.
+    Around,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Emitter {
+    pub mode: EmitterMode,
+    pub aim: Aim,
+    // How far sparks stray from the aim, in degrees either way.
+    pub spread: u16,
+    // Range of spark speeds, in subpixels per tick.
+    pub speed: (i32, i32),
+    // Range of spark lifetimes, in ticks; at least 1.
+    pub lifetime: (u16, u16),
+    // Colors and glyphs a spark goes through over its lifetime, in equal shares.
+    pub colors: &'static [Color],
+    pub glyphs: &'static [char],
+}
+
+impl Emitter {
+    // A rocket's exhaust: a stream of sparks cooling from yellow to grey.
+    pub fn exhaust() -> Self {
+        Self {
+            mode: EmitterMode::Continuous { rate: 100 },
+            aim: Aim::Exhaust,
+            spread: 20,
+            speed: (24, 48),
+            lifetime: (6, 12),
+            colors: &[Color::Yellow, Color::Red, Color::DarkRed, Color::DarkGrey],
+            glyphs: &['*', '+', '·', '.'],
+        }
+    }
+
+    // An explosion: one burst of debris flying off in every direction.
+    pub fn explosion() -> Self {
+        Self {
+            mode: EmitterMode::Burst { count: 16 },
+            aim: Aim::Around,
+            spread: 180,
+            speed: (16, 64),
+            lifetime: (8, 16),
+            colors: &[Color::White, Color::Yellow, Color::Red, Color::DarkGrey],
+            glyphs: &['#', '*', '+', '.'],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Spark {
+    position: Coordinate,
+    velocity: Coordinate,
+    age: u16,
+    // Zero for a free slot of the pool.
+    lifetime: u16,
+    colors: &'static [Color],
+    glyphs: &'static [char],
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Attached {
+    host: ParticleId,
+    emitter: Emitter,
+    // Hundredths of a spark owed from earlier ticks, see `EmitterMode::Continuous`.
+    carry: u32,
+}
+
+// The emitters of a scene and the sparks they have emitted.
+#[derive(Clone, Debug)]
+pub struct Emitters {
+    attached: Vec<Attached>,
+    sparks: Vec<Spark>,
+    // Indices of the free slots in `sparks`.
+    free: Vec<usize>,
+    rng: StdRng,
+}
+
+impl Default for Emitters {
+    fn default() -> Self {
+        Self {
+            attached: Vec::new(),
+            sparks: Vec::new(),
+            free: Vec::new(),
+            rng: StdRng::seed_from_u64(SPARK_SEED),
+        }
+    }
+}
+
+impl Emitters {
+    // Emit from a particle's position for as long as it is in the scene, or in the case
+    // of a burst, on the next tick.
+    pub fn attach(&mut self, host: ParticleId, emitter: Emitter) {
+        self.attached.push(Attached {
+            host,
+            emitter,
+            carry: 0,
+        });
+    }
+
+    // Emit a burst's sparks from `position` right away; continuous emitters emit nothing.
+    pub fn burst(&mut self, position: Coordinate, emitter: &Emitter) {
+        if let EmitterMode::Burst { count } = emitter.mode {
+            self.emit(emitter, position, 0.0, count as u32);
+        }
+    }
+
+    // One tick: the attached emitters emit, dropping those whose host is gone and bursts
+    // that went off, then every spark moves and ages.
+    pub fn step<'a, F: Fn(ParticleId) -> Option<&'a Particle>>(&mut self, host: F) {
+        let mut attached = std::mem::take(&mut self.attached);
+        attached.retain_mut(|a| {
+            let Some(p) = host(a.host) else {
+                return false;
+            };
+            let aim = match a.emitter.aim {
+                Aim::Exhaust if p.thrust == Coordinate::new(0, 0) => None,
+                Aim::Exhaust => Some((-p.thrust.y as f32).atan2(-p.thrust.x as f32)),
+                Aim::Around => Some(0.0),
+            };
+            match a.emitter.mode {
+                EmitterMode::Continuous { rate } => {
+                    let Some(aim) = aim else {
+                        a.carry = 0;
+                        return true;
+                    };
+                    a.carry += rate;
+                    self.emit(&a.emitter, p.position, aim, a.carry / 100);
+                    a.carry %= 100;
+                    true
+                }
+                EmitterMode::Burst { count } => {
+                    self.emit(&a.emitter, p.position, aim.unwrap_or(0.0), count as u32);
+                    false
+                }
+            }
+        });
+        self.attached = attached;
+
+        for (i, spark) in self.sparks.iter_mut().enumerate() {
+            if spark.lifetime == 0 {
+                continue;
+            }
+            spark.position += spark.velocity;
+            spark.age += 1;
+            if spark.age >= spark.lifetime {
+                spark.lifetime = 0;
+                self.free.push(i);
+            }
+        }
+    }
+
+    fn emit(&mut self, emitter: &Emitter, origin: Coordinate, aim: f32, count: u32) {
+        let spread = (emitter.spread as f32).to_radians();
+        for _ in 0..count {
+            let slot = match self.free.pop() {
+                Some(slot) => slot,
+                None if self.sparks.len() < MAX_SPARKS => {
+                    self.sparks.push(Spark {
+                        position: origin,
+                        velocity: Coordinate::new(0, 0),
+                        age: 0,
+                        lifetime: 0,
+                        colors: emitter.colors,
+                        glyphs: emitter.glyphs,
+                    });
+                    self.sparks.len() - 1
+                }
+                None => return,
+            };
+            let angle = aim + self.rng.random_range(-spread..=spread);
+            let speed = self.rng.random_range(emitter.speed.0..=emitter.speed.1) as f32;
+            self.sparks[slot] = Spark {
+                position: origin,
+                velocity: Coordinate::new(
+                    (angle.cos() * speed).round() as i32,
+                    (angle.sin() * speed).round() as i32,
+                ),
+                age: 0,
+                lifetime: self.rng.random_range(emitter.lifetime.0..=emitter.lifetime.1).max(1),
+                colors: emitter.colors,
+                glyphs: emitter.glyphs,
+            };
+        }
+    }
+
+    // Every live spark as its cell, glyph and colors for its age.
+    pub fn renderable(&self) -> impl Iterator<Item = (ConsoleCell, char, ParticleColors)> + '_ {
+        self.sparks.iter().filter(|s| s.lifetime > 0).map(|s| {
+            let stage = |len: usize| s.age as usize * len / s.lifetime as usize;
+            let colors = ParticleColors {
+                foreground: s.colors[stage(s.colors.len())],
+                background: Color::Black,
+            };
+            (s.position.to_cell(), s.glyphs[stage(s.glyphs.len())], colors)
+        })
+    }
+}
@@ -0,0 +1,77 @@
+// Force fields: gravity, attractors and drag acting on every particle as it updates.
+use crossterm::style::Color;
+
+use crate::particle::{Particle, ParticleColors};
+use crate::spatial::{ConsoleCell, Coordinate, SUBPIXEL_SCALE};
+
+// Field accelerations are in 1/FORCE_SCALE subpixels per step per step, so that gentle
+// forces still add up; particles carry the fraction that has not reached their velocity.
+pub const FORCE_SCALE: i32 = 256;
+
+// Attractors are treated as at least half a cell away, so that a particle passing right
+// over one is not flung off at an absurd speed.
+const MIN_DISTANCE: i64 = (SUBPIXEL_SCALE / 2) as i64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub enum ForceField {
+    // The same acceleration everywhere, such as gravity.
+    Uniform { acceleration: Coordinate },
+    // Pulls particles towards `position` (pushes them away if `strength` is negative)
+    // with inverse-square falloff: one cell away the acceleration is `strength`.
+    Point { position: Coordinate, strength: i32 },
+    // Takes `per_mille` thousandths of every particle's velocity away each step.
+    Drag { per_mille: u16 },
+}
+
+impl ForceField {
+    // The acceleration this field gives a particle, in 1/FORCE_SCALE subpixels per step
+    // per step.
+    pub fn acceleration(&self, particle: &Particle) -> Coordinate {
+        match *self {
+            ForceField::Uniform { acceleration } => acceleration,
+            ForceField::Point { position, strength } => {
+                let dx = (position.x - particle.position.x) as i64;
+                let dy = (position.y - particle.position.y) as i64;
+                let distance_sq = (dx * dx + dy * dy).max(MIN_DISTANCE * MIN_DISTANCE);
+                let distance = distance_sq.isqrt();
+                // strength · (cell / distance)² along the unit vector (dx, dy) / distance.
+                let scale = SUBPIXEL_SCALE as i64;
+                let magnitude = strength as i64 * scale * scale;
+                let denominator = distance_sq * distance;
+                Coordinate::new(
+                    (magnitude * dx / denominator) as i32,
+                    (magnitude * dy / denominator) as i32,
+                )
+            }
+            ForceField::Drag { per_mille } => {
+                let drag = |v: i32| -(v as i64 * per_mille as i64 * FORCE_SCALE as i64 / 1000) as i32;
+                Coordinate::new(drag(particle.velocity.x), drag(particle.velocity.y))
+            }
+        }
+    }
+
+    // What to draw for the field, if it has a place on the map: attractors as `@` and
+    // repulsors as `%`. Uniform fields and drag are everywhere and so drawn nowhere.
+    pub fn map_feature(&self) -> Option<(ConsoleCell, char, ParticleColors)> {
+        match *self {
+            ForceField::Point { position, strength } => Some((
+                position.to_cell(),
+                if strength >= 0 { '@' } else { '%' },
+                ParticleColors {
+                    foreground: Color::Magenta,
+                    background: Color::Black,
+                },
+            )),
+            ForceField::Uniform { .. } | ForceField::Drag { .. } => None,
+        }
+    }
+}
+
+// The combined acceleration of all fields on a particle.
+pub fn total_acceleration(fields: &[ForceField], particle: &Particle) -> Coordinate {
+    let mut total = Coordinate::default();
+    for field in fields {
+        total += field.acceleration(particle);
+    }
+    total
+}
@@ -0,0 +1,111 @@
+// Double-buffered frame: draw into the back buffer, then present only the cells that changed.
+use crate::particle::ParticleColors;
+use crate::render::{DEFAULT_COLORS, RenderBackend};
+use crate::spatial::ConsoleCell;
+
+pub type FrameCell = (char, ParticleColors);
+
+const BLANK: FrameCell = (' ', DEFAULT_COLORS);
+
+pub struct FrameBuffer {
+    width: u16,
+    height: u16,
+    // What is currently on screen.
+    front: Vec<FrameCell>,
+    // What the next present should put on screen.
+    back: Vec<FrameCell>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: u16, height: u16) -> Self {
+        let len = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            front: vec![BLANK; len],
+            back: vec![BLANK; len],
+        }
+    }
+
+    fn index(&self, cell: ConsoleCell) -> Option<usize> {
+        if cell.x < self.width && cell.y < self.height {
+            Some(cell.y as usize * self.width as usize + cell.x as usize)
+        } else {
+            None
+        }
+    }
+
+    // Write one cell into the back buffer (ignored if out of bounds).
+    pub fn set(&mut self, cell: ConsoleCell, ch: char, colors: ParticleColors) {
+        if let Some(i) = self.index(cell) {
+            self.back[i] = (ch, colors);
+        }
+    }
+
+    // Write a string into the back buffer starting at `cell`, clipped at the right edge.
+    pub fn put_str(&mut self, cell: ConsoleCell, s: &str, colors: ParticleColors) {
+        for (i, ch) in s.chars().enumerate() {
+            self.set(ConsoleCell::new(cell.x + i as u16, cell.y), ch, colors);
+        }
+    }
+
+    // Blank a rectangle of the back buffer; `to` is exclusive.
+    pub fn clear_rect(&mut self, from: ConsoleCell, to: ConsoleCell) {
+        for y in from.y..to.y.min(self.height) {
+            for x in from.x..to.x.min(self.width) {
+                self.set(ConsoleCell::new(x, y), BLANK.0, BLANK.1);
+            }
+        }
+    }
+
+    // Clear the whole screen and the back buffer; the front buffer then matches a blank screen.
+    pub fn clear_screen<B: RenderBackend>(&mut self, backend: &mut B) {
+        backend.set_colors(DEFAULT_COLORS);
+        backend.clear();
+        self.front.fill(BLANK);
+        self.back.fill(BLANK);
+    }
+
+    // Emit the difference between the back and front buffers, then make them equal.
+    // Consecutive changed cells on a row that share colors are sent as a single run,
+    // and colors are only re-sent when they differ from the last ones sent.
+    pub fn present<B: RenderBackend>(&mut self, backend: &mut B) {
+        let mut current_colors: Option<ParticleColors> = None;
+        let mut run = String::new();
+
+        for y in 0..self.height {
+            let mut x = 0;
+            while x < self.width {
+                let i = y as usize * self.width as usize + x as usize;
+                if self.back[i] == self.front[i] {
+                    x += 1;
+                    continue;
+                }
+
+                let run_start = x;
+                let run_colors = self.back[i].1;
+                run.clear();
+                while x < self.width {
+                    let j = y as usize * self.width as usize + x as usize;
+                    if self.back[j] == self.front[j] || self.back[j].1 != run_colors {
+                        break;
+                    }
+                    run.push(self.back[j].0);
+                    self.front[j] = self.back[j];
+                    x += 1;
+                }
+
+                if current_colors != Some(run_colors) {
+                    backend.set_colors(run_colors);
+                    current_colors = Some(run_colors);
+                }
+                backend.put_str(ConsoleCell::new(run_start, y), &run);
+            }
+        }
+
+        if current_colors.is_some_and(|c| c != DEFAULT_COLORS) {
+            backend.set_colors(DEFAULT_COLORS);
+        }
+        backend.flush();
+    }
+}
@@ -1,10 +1,39 @@
 // Defines game event types and their associated payloads.
+use crate::boundary::Edge;
+use crate::particle::ParticleId;
+use crate::spatial::{ConsoleCell, Coordinate};
 
 #[derive(Clone, Debug)]
 pub enum GameEvent {
-    // A refuel event between a rocket and a fuel cell, identified by their indices in the scene.
+    // A refuel event between a rocket and a fuel cell, identified by their stable IDs.
     Refuel {
-        rocket_idx: usize,
-        fuel_cell_idx: usize,
+        rocket: ParticleId,
+        fuel_cell: ParticleId,
+    },
+    // Two particles ran into each other in `cell`; `relative_velocity` is a's minus b's.
+    #[allow(dead_code)]
+    Impact {
+        a: ParticleId,
+        b: ParticleId,
+        cell: ConsoleCell,
+        relative_velocity: Coordinate,
+    },
+    // A particle reached the edge of the playfield and its boundary mode was applied.
+    #[allow(dead_code)]
+    BorderHit { id: ParticleId, edge: Edge },
+    // A particle left the playfield through a despawning edge or finished a one-shot
+    // animation that despawns it; it takes no part in that step's collisions and is
+    // removed at the end of the step.
+    #[allow(dead_code)]
+    Despawned { id: ParticleId },
+    // A particle reached the end of its lifetime; it takes no part in that step's
+    // collisions and is removed at the end of the step.
+    #[allow(dead_code)]
+    Expired { id: ParticleId },
+    // A particle collected another.
+    #[allow(dead_code)]
+    Pickup {
+        collector: ParticleId,
+        item: ParticleId,
     },
 }
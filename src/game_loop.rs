@@ -0,0 +1,54 @@
+// Fixed-timestep clock: the simulation advances in equal steps no matter how often
+// the loop around it wakes up for input or rendering.
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TICK_RATE: u32 = 40;
+// Upper bound on steps run for a single frame, so a long stall (a suspended process,
+// a slow terminal) does not turn into a burst of catch-up steps.
+const MAX_STEPS_PER_FRAME: u32 = 5;
+
+pub struct FixedTimestep {
+    tick_rate: u32,
+    step: Duration,
+    accumulator: Duration,
+    last: Instant,
+}
+
+impl FixedTimestep {
+    // `tick_rate` is in simulation steps per second and must be non-zero.
+    pub fn new(tick_rate: u32) -> Self {
+        Self {
+            tick_rate,
+            step: Duration::from_secs(1) / tick_rate,
+            accumulator: Duration::ZERO,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn tick_rate(&self) -> u32 {
+        self.tick_rate
+    }
+
+    // How long until the next step is due, measured from the last call to `advance`.
+    pub fn until_next_step(&self) -> Duration {
+        (self.step.saturating_sub(self.accumulator)).saturating_sub(self.last.elapsed())
+    }
+
+    // Account for the time passed since the last call and return how many steps to run.
+    pub fn advance(&mut self) -> u32 {
+        let now = Instant::now();
+        self.accumulator += now - self.last;
+        self.last = now;
+
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps > MAX_STEPS_PER_FRAME {
+            self.accumulator = Duration::ZERO;
+            steps = MAX_STEPS_PER_FRAME;
+        }
+        steps
+    }
+}
@@ -0,0 +1,126 @@
+// Headless batch runs: step the scene with no terminal and print a summary.
+use std::collections::BTreeMap;
+
+use crate::cli::Options;
+use crate::config::Config;
+use crate::game_events::GameEvent;
+use crate::replay::{Replay, ReplayEntry, ReplayHeader, ReplayRecorder, settings_hash};
+use crate::scene::Scene;
+use crate::spawn::Spawner;
+use crate::sprite::SpriteRegistry;
+
+// Run the simulation and print the final state to stdout. Without a replay this runs
+// `options.ticks` steps with no input; with one it feeds back the recorded boosts.
+pub fn run(options: &Options) -> Result<(), String> {
+    let config = Config::load(options.config.as_deref())?;
+    let sprites = SpriteRegistry::load(options.sprites.as_deref())?;
+    let settings = settings_hash(&config, &sprites);
+    let replay = match &options.replay {
+        Some(path) => Some(Replay::load(path, settings)?),
+        None => None,
+    };
+    let header = match &replay {
+        Some(r) => r.header,
+        None => ReplayHeader {
+            seed: options.seed.unwrap_or_else(rand::random),
+            width: options.width,
+            height: options.height,
+            tick_rate: options.tick_rate,
+            population: options.population,
+            player_control: config.player_control,
+            settings,
+        },
+    };
+    let (mut cell_width, mut cell_height) = (header.width, header.height);
+    // Without a replay, idle steps are made as they are needed, so long runs take no
+    // memory up front.
+    let entries: Box<dyn Iterator<Item = ReplayEntry>> = match replay {
+        Some(r) => Box::new(r.entries.into_iter()),
+        None => Box::new(std::iter::repeat_n(ReplayEntry::Step(vec![]), options.ticks as usize)),
+    };
+
+    let mut spawner = Spawner::new(header.seed);
+    let mut scene = Scene::new(vec![]);
+    scene.set_collision_matrix(config.collisions);
+    scene.set_boundaries(config.boundaries);
+    scene.set_sprites(sprites);
+    for field in config.fields {
+        scene.add_field(field);
+    }
+    let mut particles =
+        spawner.initial_particles(cell_width, cell_height, &header.population, scene.sprites());
+    // The player's rocket comes first.
+    particles[0].control = header.player_control;
+    for particle in particles {
+        scene.add_particle(particle);
+    }
+
+    let mut recorder = match &options.record {
+        Some(path) => {
+            Some(ReplayRecorder::create(path, &header).map_err(|e| format!("{}: {}", path, e))?)
+        }
+        None => None,
+    };
+    let record_error = |e: std::io::Error| format!("Failed to write replay: {}", e);
+
+    let mut ticks: u64 = 0;
+    let mut event_counts: BTreeMap<&'static str, u64> = BTreeMap::new();
+
+    for entry in entries {
+        let boosts = match entry {
+            ReplayEntry::Resize(w, h) => {
+                cell_width = w;
+                cell_height = h;
+                scene.clamp_into(w, h);
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record_resize(w, h).map_err(record_error)?;
+                }
+                continue;
+            }
+            ReplayEntry::Step(boosts) => boosts,
+        };
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record_step(&boosts).map_err(record_error)?;
+        }
+
+        let events = scene.step(cell_width, cell_height, &boosts);
+        ticks += 1;
+
+        for e in events.iter() {
+            *event_counts.entry(event_name(e)).or_insert(0) += 1;
+        }
+    }
+
+    println!(
+        "Ran {} ticks with seed {} on a {}x{} playfield.",
+        ticks,
+        spawner.seed(),
+        cell_width,
+        cell_height
+    );
+    println!();
+    println!("Particles ({}):", scene.particles().len());
+    for p in scene.particles().iter() {
+        println!("  #{} {:?}: {}. Fuel: {}", p.uid.0, p.kind, p, p.fuel);
+    }
+    println!();
+    let total_events: u64 = event_counts.values().sum();
+    println!("Events ({}):", total_events);
+    for (name, count) in event_counts.iter() {
+        println!("  {}: {}", name, count);
+    }
+    println!();
+    println!("Fuel consumed: {}", scene.fuel_consumed());
+    Ok(())
+}
+
+fn event_name(event: &GameEvent) -> &'static str {
+    match event {
+        GameEvent::Refuel { .. } => "Refuel",
+        GameEvent::Impact { .. } => "Impact",
+        GameEvent::Pickup { .. } => "Pickup",
+        GameEvent::BorderHit { .. } => "BorderHit",
+        GameEvent::Despawned { .. } => "Despawned",
+        GameEvent::Expired { .. } => "Expired",
+    }
+}
@@ -0,0 +1,158 @@
+// Input models: how "held" is decided for an action.
+//
+// Terminals that honour keyboard enhancement report key releases, so an action is held
+// from press to release. Others only send presses (and auto-repeat presses while a key
+// is down), so there an action counts as released once no repeat arrived for a while:
+// a long while after the press, which has to bridge the delay before auto-repeat starts,
+// and only a short one once repeats are coming in.
+use crossterm::event::KeyCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::keymap::Action;
+
+// Long enough to bridge the delay before a terminal starts auto-repeating, which is
+// 660 ms by default under X11; a tapped key lingers for this long after its release.
+pub const DEFAULT_REPEAT_DELAY: Duration = Duration::from_millis(750);
+// A few repeat intervals at the usual rates of 25 to 30 a second; a key held long enough
+// to repeat lingers for this long after its release.
+pub const DEFAULT_REPEAT_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputMode {
+    // Held from press until the matching release event.
+    KeyRelease,
+    // Held until no repeat has been seen for `delay` after the press, or for `timeout`
+    // after a repeat.
+    RepeatTimeout { delay: Duration, timeout: Duration },
+}
+
+// The input mode as requested in the config file.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputModeSetting {
+    // Key release when the terminal supports keyboard enhancement, repeat timeout otherwise.
+    #[default]
+    Auto,
+    KeyRelease,
+    RepeatTimeout,
+}
+
+impl InputMode {
+    pub fn choose(
+        setting: InputModeSetting,
+        key_release_supported: bool,
+        delay: Duration,
+        timeout: Duration,
+    ) -> Self {
+        match setting {
+            InputModeSetting::KeyRelease => InputMode::KeyRelease,
+            InputModeSetting::Auto if key_release_supported => InputMode::KeyRelease,
+            InputModeSetting::RepeatTimeout | InputModeSetting::Auto => {
+                InputMode::RepeatTimeout { delay, timeout }
+            }
+        }
+    }
+}
+
+impl Display for InputMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputMode::KeyRelease => write!(f, "key release"),
+            InputMode::RepeatTimeout { delay, timeout } => write!(
+                f,
+                "repeat timeout {}/{}ms",
+                delay.as_millis(),
+                timeout.as_millis()
+            ),
+        }
+    }
+}
+
+// Which actions are currently held, tracked per key so that releasing one of two
+// keys bound to the same action does not release the action.
+pub struct HeldActions {
+    mode: InputMode,
+    // When each held key was last pressed or repeated, and whether it has repeated since
+    // it was pressed.
+    held: HashMap<Action, HashMap<KeyCode, (Instant, bool)>>,
+}
+
+impl HeldActions {
+    pub fn new(mode: InputMode) -> Self {
+        Self {
+            mode,
+            held: HashMap::new(),
+        }
+    }
+
+    pub fn mode(&self) -> InputMode {
+        self.mode
+    }
+
+    // A press or an auto-repeat of `key`; without release events the two look the same,
+    // so a press of a key that is still held counts as a repeat.
+    pub fn press(&mut self, action: Action, key: KeyCode) {
+        let repeat = self.key_held(action, key);
+        self.held
+            .entry(action)
+            .or_default()
+            .insert(key, (Instant::now(), repeat));
+    }
+
+    pub fn release(&mut self, action: Action, key: KeyCode) {
+        if let Some(keys) = self.held.get_mut(&action) {
+            keys.remove(&key);
+        }
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        self.held
+            .get(&action)
+            .is_some_and(|keys| keys.keys().any(|key| self.key_held(action, *key)))
+    }
+
+    fn key_held(&self, action: Action, key: KeyCode) -> bool {
+        let Some(&(seen, repeated)) = self.held.get(&action).and_then(|keys| keys.get(&key)) else {
+            return false;
+        };
+        match self.mode {
+            InputMode::KeyRelease => true,
+            InputMode::RepeatTimeout { timeout, .. } if repeated => seen.elapsed() < timeout,
+            InputMode::RepeatTimeout { delay, .. } => seen.elapsed() < delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn repeats_shorten_the_timeout() {
+        let mut held = HeldActions::new(InputMode::RepeatTimeout {
+            delay: Duration::from_secs(5),
+            timeout: Duration::from_millis(30),
+        });
+        let key = KeyCode::Char('b');
+
+        // Until the first repeat the long delay applies...
+        held.press(Action::Brake, key);
+        sleep(Duration::from_millis(60));
+        assert!(held.is_held(Action::Brake));
+
+        // ...and after it the short timeout.
+        held.press(Action::Brake, key);
+        assert!(held.is_held(Action::Brake));
+        sleep(Duration::from_millis(60));
+        assert!(!held.is_held(Action::Brake));
+
+        // A press after the key lapsed starts over with the delay.
+        held.press(Action::Brake, key);
+        sleep(Duration::from_millis(60));
+        assert!(held.is_held(Action::Brake));
+    }
+}
@@ -0,0 +1,108 @@
+// Key bindings: maps raw key codes to game actions, several keys per action allowed.
+use crossterm::event::KeyCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    ThrustUp,
+    ThrustDown,
+    ThrustLeft,
+    ThrustRight,
+    Brake,
+    Quit,
+    Pause,
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    bindings: HashMap<KeyCode, Action>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut keys = Self {
+            bindings: HashMap::new(),
+        };
+        keys.bind(Action::ThrustUp, &[KeyCode::Up]);
+        keys.bind(Action::ThrustDown, &[KeyCode::Down]);
+        keys.bind(Action::ThrustLeft, &[KeyCode::Left]);
+        keys.bind(Action::ThrustRight, &[KeyCode::Right]);
+        keys.bind(Action::Brake, &[KeyCode::Char('b')]);
+        keys.bind(Action::Quit, &[KeyCode::Char('q')]);
+        keys.bind(Action::Pause, &[KeyCode::Char('p')]);
+        keys
+    }
+}
+
+impl KeyBindings {
+    // Replace whatever keys were bound to `action` with `keys`. A key can only trigger
+    // one action, so binding it here takes it away from any other action.
+    pub fn bind(&mut self, action: Action, keys: &[KeyCode]) {
+        self.bindings.retain(|_, a| *a != action);
+        for key in keys {
+            self.bindings.insert(*key, action);
+        }
+    }
+
+    pub fn action_for(&self, key: KeyCode) -> Option<Action> {
+        self.bindings.get(&key).copied()
+    }
+
+    pub fn is_bound(&self, action: Action) -> bool {
+        self.bindings.values().any(|a| *a == action)
+    }
+}
+
+// Parse a key name as written in the config file: a single character such as "w",
+// or a named key such as "Up", "Space", "Esc" or "F1".
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(ch));
+    }
+    let key = match name.to_ascii_lowercase().as_str() {
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        "enter" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        other => {
+            let n: u8 = other.strip_prefix('f')?.parse().ok()?;
+            KeyCode::F(n)
+        }
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_characters_and_named_keys() {
+        assert_eq!(parse_key("w"), Some(KeyCode::Char('w')));
+        assert_eq!(parse_key("W"), Some(KeyCode::Char('W')));
+        assert_eq!(parse_key("Space"), Some(KeyCode::Char(' ')));
+        assert_eq!(parse_key("up"), Some(KeyCode::Up));
+        assert_eq!(parse_key("Escape"), Some(KeyCode::Esc));
+        assert_eq!(parse_key("F12"), Some(KeyCode::F(12)));
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert_eq!(parse_key(""), None);
+        assert_eq!(parse_key("Hyper"), None);
+        assert_eq!(parse_key("F256"), None);
+        assert_eq!(parse_key("Fx"), None);
+    }
+}
@@ -1,247 +1,393 @@
+mod bench;
 mod border;
+mod boundary;
 mod console;
+mod frame;
 mod particle;
+mod render;
+mod replay;
+mod rules;
 mod spatial;
 mod scene;
+mod spawn;
+mod sprite;
+mod spatial_hash;
+mod cli;
 mod collision;
+mod commands;
+mod config;
+mod emitter;
+mod force;
 mod game_events;
+mod game_loop;
+mod headless;
+mod input;
+mod keymap;
+mod terminal;
 
-use crate::console::{Console, DEFAULT_BACKGROUND_COLOR, DEFAULT_FOREGROUND_COLOR};
-use crate::particle::{Particle, ParticleType, Boost};
-use crate::spatial::{Coordinate, SUBPIXEL_SCALE};
-use crossterm::event::{
-    Event, KeyCode, KeyEventKind, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
-    PushKeyboardEnhancementFlags, poll, read,
-};
-use crossterm::execute;
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
-use std::io::{Write, stdout};
+use crate::cli::{Options, USAGE};
+use crate::config::Config;
+use crate::console::{Console, InfoPane, INFO_PANE_WIDTH};
+use crate::input::{HeldActions, InputMode};
+use crate::keymap::Action;
+use crate::particle::{Boost, ControlModel, ParticleId};
+use crate::render::{CrosstermBackend, RenderBackend};
+use crate::replay::{Replay, ReplayEntry, ReplayHeader, ReplayRecorder, settings_hash};
+use crate::spatial::Coordinate;
+use crate::spawn::Spawner;
+use crate::emitter::Emitter;
+use crate::sprite::SpriteRegistry;
+use crossterm::event::{Event, KeyEventKind, poll, read};
 use std::time::Duration;
-use crossterm::style::{Color, Colors, SetColors};
-use crate::game_events::GameEvent;
+use crate::game_loop::FixedTimestep;
+use crate::terminal::TerminalSession;
 
-fn main() {
-    let mut stdout = stdout();
-    enable_raw_mode().expect("Failed to enable raw mode");
-    execute!(
-        stdout,
-        PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES,),
-        PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES),
-        SetColors(Colors::new(
-            DEFAULT_FOREGROUND_COLOR,
-            DEFAULT_BACKGROUND_COLOR
-        ))
+// Margin kept between the playfield and the information pane.
+const MARGIN: u16 = 2;
+// Smallest playfield that still has an interior cell to move in.
+const MIN_FIELD_SIZE: u16 = 3;
+
+// The playfield size that fits a terminal of the given size next to the info pane.
+fn playfield_size(term_w: u16, term_h: u16) -> (u16, u16) {
+    (
+        term_w
+            .saturating_sub(MARGIN + INFO_PANE_WIDTH)
+            .max(MIN_FIELD_SIZE),
+        term_h.max(MIN_FIELD_SIZE),
     )
-    .expect("Failed to set keyboard enhancement flags");
-    let info_pane_width = 32;
-    let margin = 2;
-    // detect the length of terminal
-    let (term_w, term_h) = crossterm::terminal::size().unwrap();
-    let mut console = Console::new(term_w - (margin +  info_pane_width), term_h);
+}
 
-    // Init the map
-    console.draw_borders();
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Some(count) = options.bench_collisions {
+        if let Err(message) = bench::run(count, options.seed.unwrap_or_else(rand::random)) {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if options.headless {
+        if let Err(message) = headless::run(&options) {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config = Config::load(options.config.as_deref()).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    });
+    let sprites = SpriteRegistry::load(options.sprites.as_deref()).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    });
 
-    // Init a single particle
-    let mut particle = Particle::new(
-        Some(Coordinate::new(
-            ((rand::random::<u16>() % (term_w - 2)) + 1) as i32 * SUBPIXEL_SCALE,
-            ((rand::random::<u16>() % (term_h - 2)) + 1) as i32 * SUBPIXEL_SCALE,
-        )),
-        None,
-        None,
-        ParticleType::Rocket,
-        Coordinate::new(200, 200),
-    );
-
-    // Init a single fuel cell with random velocity
-    let fuel_cell = Particle::new(
-        Some(Coordinate::new(
-            ((rand::random::<u16>() % (term_w - 2)) + 1) as i32 * SUBPIXEL_SCALE,
-            ((rand::random::<u16>() % (term_h - 2)) + 1) as i32 * SUBPIXEL_SCALE,
-        )),
-        Some(Coordinate::new(
-            {
-                let mut vx = (rand::random::<i16>() % 2) as i32 * (SUBPIXEL_SCALE / 4);
-                if vx == 0 { vx = SUBPIXEL_SCALE / 4; }
-                vx
-            },
-            {
-                let mut vy = (rand::random::<i16>() % 2) as i32 * (SUBPIXEL_SCALE / 4);
-                if vy == 0 { vy = SUBPIXEL_SCALE / 4; }
-                vy
+    // A replay dictates the seed, playfield size, tick rate, population and player control
+    // it was recorded with, and only plays back under the settings it was recorded with.
+    let settings = settings_hash(&config, &sprites);
+    let replay = options.replay.as_ref().map(|path| {
+        Replay::load(path, settings).unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        })
+    });
+    let header = match &replay {
+        Some(r) => r.header,
+        None => {
+            // detect the length of terminal
+            let (term_w, term_h) = crossterm::terminal::size().unwrap();
+            let (width, height) = playfield_size(term_w, term_h);
+            ReplayHeader {
+                seed: options.seed.unwrap_or_else(rand::random),
+                width,
+                height,
+                tick_rate: options.tick_rate,
+                population: options.population,
+                player_control: config.player_control,
+                settings,
             }
-        )),
-        None,
-        ParticleType::FuelCell,
-        Coordinate::new(200, 200),
-    );
+        }
+    };
+    let ReplayHeader {
+        seed,
+        width: field_w,
+        height: field_h,
+        tick_rate,
+        population,
+        player_control,
+        ..
+    } = header;
+    let mut recorder = options.record.as_ref().map(|path| {
+        ReplayRecorder::create(path, &header).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    let replay_steps = replay.as_ref().map_or(0, |r| r.step_count());
+    let mut replay_entries = replay.map(|r| r.entries.into_iter());
+    let mut replayed_steps = 0;
 
-    particle.set_color(Color::Red);
+    // Restores the terminal when it goes out of scope, including on panic.
+    let session = TerminalSession::enter().expect("Failed to set up the terminal");
 
-    // Add particles to the console's scene (initial state)
-    console.add_particle(particle);
-    console.add_particle(fuel_cell);
+    let mut console = Console::new(field_w, field_h, CrosstermBackend::new());
+    console.set_collision_matrix(config.collisions.clone());
+    console.set_boundaries(config.boundaries.clone());
+    console.set_sprites(sprites.clone());
+    for field in config.fields.iter() {
+        console.add_field(*field);
+    }
 
-    let mut interrupt_flag = false;
-    // Listener for keydown on escape and exit
+    // Init the map
+    console.draw_borders();
 
-    let mut up_held = false;
-    let mut down_held = false;
-    let mut left_held = false;
-    let mut right_held = false;
-    let mut braking_held = false;
+    // Init the rocket and a fuel cell (scene order: [rocket, fuel])
+    let mut spawner = Spawner::new(seed);
+    let mut particles = spawner.initial_particles(field_w, field_h, &population, &sprites);
+    // The player's rocket comes first and is the one the information pane follows.
+    particles[0].control = player_control;
+    let ids: Vec<ParticleId> = particles
+        .into_iter()
+        .map(|particle| console.add_particle(particle))
+        .collect();
+    let player = ids[0];
+    console.attach_emitter(player, Emitter::exhaust());
 
+    let mut interrupt_flag = false;
+    let mut paused = false;
+    let mut held = HeldActions::new(InputMode::choose(
+        config.input_mode,
+        session.keyboard_enhancement(),
+        config.repeat_delay,
+        config.repeat_timeout,
+    ));
     let mut cycle_phase: u8 = 0; // 0..=15
 
+    let mut timestep = FixedTimestep::new(tick_rate);
+    let mut pressed_str = String::new();
+
     while !interrupt_flag {
-        if poll(Duration::from_millis(25)).unwrap() {
+        // Wait for input or until the next step is due, then drain whatever else is queued
+        // so that bursts of key-repeat events never hold up the simulation.
+        let mut timeout = timestep.until_next_step();
+        while poll(timeout).unwrap() {
+            timeout = Duration::ZERO;
             match read().unwrap() {
-                Event::Key(event) => match event.code {
-                    KeyCode::Char('q') => {
-                        interrupt_flag = true;
-                    }
-                    KeyCode::Char('b') => {
-                        if event.kind == KeyEventKind::Press {
-                            braking_held = true;
-                        } else if event.kind == KeyEventKind::Release {
-                            braking_held = false;
-                        }
-                    }
-                    KeyCode::Up => {
-                        if event.kind == KeyEventKind::Press {
-                            up_held = true;
-                        } else if event.kind == KeyEventKind::Release {
-                            up_held = false;
+                Event::Resize(w, h) => {
+                    if replay_entries.is_some() {
+                        // The replay fixes the playfield size; just repaint the screen.
+                        console.draw_borders();
+                    } else {
+                        let (field_w, field_h) = playfield_size(w, h);
+                        console.resize(field_w, field_h);
+                        if let Some(recorder) = recorder.as_mut() {
+                            recorder
+                                .record_resize(field_w, field_h)
+                                .expect("Failed to write replay");
                         }
                     }
-                    KeyCode::Down => {
-                        if event.kind == KeyEventKind::Press {
-                            down_held = true;
-                        } else if event.kind == KeyEventKind::Release {
-                            down_held = false;
-                        }
-                    }
-                    KeyCode::Left => {
-                        if event.kind == KeyEventKind::Press {
-                            left_held = true;
-                        } else if event.kind == KeyEventKind::Release {
-                            left_held = false;
-                        }
-                    }
-                    KeyCode::Right => {
-                        if event.kind == KeyEventKind::Press {
-                            right_held = true;
-                        } else if event.kind == KeyEventKind::Release {
-                            right_held = false;
-                        }
-                    }
-                    _ => (),
+                }
+                // Repeats refresh a held action; without keyboard enhancement every
+                // event arrives as a press, and the input mode decides when it lapses.
+                Event::Key(event) => match (config.keys.action_for(event.code), event.kind) {
+                    (Some(Action::Quit), _) => interrupt_flag = true,
+                    (Some(Action::Pause), KeyEventKind::Press) => paused = !paused,
+                    (Some(Action::Pause), _) | (None, _) => (),
+                    (Some(action), KeyEventKind::Release) => held.release(action, event.code),
+                    (Some(action), _) => held.press(action, event.code),
                 },
                 _ => (),
             }
-        } else {
-            let mut pressed_str = String::from("");
-
-            // Handle vertical movement and acceleration
-            let d_a_y = match (up_held, down_held) {
-                (true, false) => {
-                    pressed_str.push_str("↑  ");
-                    -1
-                }
-                (false, true) => {
-                    pressed_str.push_str("  ↓");
-                    1
-                }
-                (true, true) => {
-                    pressed_str.push_str("↑ ↓");
-                    0 // Both pressed, cancel out
-                }
-                (false, false) => {
-                    pressed_str.push_str("   ");
-                    0
-                }
-            };
+        }
 
-            // Handle horizontal movement and acceleration
-            let d_a_x = match (left_held, right_held) {
-                (true, false) => {
-                    pressed_str.push_str(" ←  ");
-                    -1
-                }
-                (false, true) => {
-                    pressed_str.push_str("  → ");
-                    1
-                }
-                (true, true) => {
-                    pressed_str.push_str(" ← →");
-                    0 // Both pressed, cancel out
-                }
-                (false, false) => {
-                    pressed_str.push_str("   ");
-                    0
+        let due = timestep.advance();
+        // While paused the clock keeps running, so resuming does not trigger a catch-up burst.
+        let steps = if paused { 0 } else { due };
+        for _ in 0..steps {
+            let boosts = match replay_entries.as_mut() {
+                Some(entries) => match next_replay_step(entries, &mut console) {
+                    Some(boosts) => {
+                        replayed_steps += 1;
+                        pressed_str = format!("Replay {}/{}", replayed_steps, replay_steps);
+                        boosts
+                    }
+                    None => {
+                        interrupt_flag = true;
+                        break;
+                    }
+                },
+                None => {
+                    cycle_phase = (cycle_phase + 1) % 16;
+                    player_boost(&held, player_control, cycle_phase, &mut pressed_str)
+                        .map(|boost| (player, boost))
+                        .into_iter()
+                        .collect()
                 }
             };
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.record_step(&boosts).expect("Failed to write replay");
+            }
 
-            cycle_phase = (cycle_phase + 1) % 16;
-
-            // Decide the per-frame boost to pass to the particle.
-            let boost = if braking_held {
-                pressed_str = String::from("Braking");
-                Some(Boost::Brake)
-            } else if cycle_phase % 4 == 0 {
-                // Throttle applying thrust vectors
-                if d_a_x != 0 || d_a_y != 0 {
-                    Some(Boost::Coordinate(Coordinate::new(d_a_x, d_a_y)))
-                } else {
-                    None
-                }
-            } else {
-                Some(Boost::Coordinate(Coordinate::new(0, 0)))
-            };
+            // Advance the simulation one step
+            console.step(&boosts);
+        }
 
-            // Update and draw via console tick (scene order: [rocket, fuel])
-            let events = console.tick(vec![
-                boost,
-                None,
-            ]);
-
-            // Handle events: collect indices to mutate
-            let mut rockets_to_refuel: Vec<usize> = Vec::new();
-            let mut fuel_cells_to_remove: Vec<usize> = Vec::new();
-
-            for e in events.iter() {
-                match e {
-                    GameEvent::Refuel { rocket_idx, fuel_cell_idx } => {
-                        rockets_to_refuel.push(*rocket_idx);
-                        fuel_cells_to_remove.push(*fuel_cell_idx);
-                    }
-                }
+        // Render once per frame, however many steps it took
+        if due > 0 {
+            if let Some(p0) = console.get_particle(player).copied() {
+                console.display_info(
+                    &p0,
+                    &InfoPane {
+                        pressed_keys: if paused { "Paused" } else { &pressed_str },
+                        tick_rate: timestep.tick_rate(),
+                        seed: spawner.seed(),
+                        input_mode: held.mode(),
+                    },
+                );
             }
+            console.render();
+        }
+    }
+}
 
-            // Refill rockets to full (capacity 510)
-            rockets_to_refuel.sort();
-            rockets_to_refuel.dedup();
-            for ri in rockets_to_refuel {
-                console.set_particle_fuel(ri, 510);
-            }
+// Decide the per-step boost for the player's rocket from the held actions, and describe
+// them in `pressed_str` for the information pane.
+fn player_boost(
+    held: &HeldActions,
+    control: ControlModel,
+    cycle_phase: u8,
+    pressed_str: &mut String,
+) -> Option<Boost> {
+    match control {
+        ControlModel::Axes => axes_boost(held, cycle_phase, pressed_str),
+        ControlModel::Heading => heading_boost(held, cycle_phase, pressed_str),
+    }
+}
 
-            // Remove fuel cells; remove in descending index order to keep indices valid
-            fuel_cells_to_remove.sort();
-            fuel_cells_to_remove.dedup();
-            fuel_cells_to_remove.sort_by(|a, b| b.cmp(a));
-            for fi in fuel_cells_to_remove {
-                console.remove_particle(fi);
+// Thrust directly along the axes the held arrows point in.
+fn axes_boost(held: &HeldActions, cycle_phase: u8, pressed_str: &mut String) -> Option<Boost> {
+    pressed_str.clear();
 
-            }
+    // Handle vertical movement and acceleration
+    let d_a_y = match (held.is_held(Action::ThrustUp), held.is_held(Action::ThrustDown)) {
+        (true, false) => {
+            pressed_str.push_str("↑  ");
+            -1
+        }
+        (false, true) => {
+            pressed_str.push_str("  ↓");
+            1
+        }
+        (true, true) => {
+            pressed_str.push_str("↑ ↓");
+            0 // Both pressed, cancel out
+        }
+        (false, false) => {
+            pressed_str.push_str("   ");
+            0
+        }
+    };
 
-            if let Some(p0) = console.get_particle(0) {
-                console.display_info(p0, &pressed_str);
-            }
-            stdout.flush().unwrap();
+    // Handle horizontal movement and acceleration
+    let d_a_x = match (held.is_held(Action::ThrustLeft), held.is_held(Action::ThrustRight)) {
+        (true, false) => {
+            pressed_str.push_str(" ←  ");
+            -1
+        }
+        (false, true) => {
+            pressed_str.push_str("  → ");
+            1
+        }
+        (true, true) => {
+            pressed_str.push_str(" ← →");
+            0 // Both pressed, cancel out
+        }
+        (false, false) => {
+            pressed_str.push_str("   ");
+            0
+        }
+    };
+
+    if held.is_held(Action::Brake) {
+        *pressed_str = String::from("Braking");
+        Some(Boost::Brake)
+    } else if cycle_phase.is_multiple_of(4) {
+        // Throttle applying thrust vectors
+        if d_a_x != 0 || d_a_y != 0 {
+            Some(Boost::Coordinate(Coordinate::new(d_a_x, d_a_y)))
+        } else {
+            None
+        }
+    } else {
+        Some(Boost::Coordinate(Coordinate::new(0, 0)))
+    }
+}
+
+// Rotate with left and right and thrust along the heading with up; down does nothing.
+fn heading_boost(held: &HeldActions, cycle_phase: u8, pressed_str: &mut String) -> Option<Boost> {
+    pressed_str.clear();
+
+    // Turn one heading step every other step, a full turn in 64 steps.
+    let turn = match (held.is_held(Action::ThrustLeft), held.is_held(Action::ThrustRight)) {
+        (true, false) => {
+            pressed_str.push_str("↺ ");
+            -1
+        }
+        (false, true) => {
+            pressed_str.push_str(" ↻");
+            1
+        }
+        (true, true) => {
+            pressed_str.push_str("↺↻");
+            0 // Both pressed, cancel out
+        }
+        (false, false) => {
+            pressed_str.push_str("  ");
+            0
+        }
+    };
+    let turn = if cycle_phase.is_multiple_of(2) { turn } else { 0 };
+
+    let thrust = if held.is_held(Action::ThrustUp) {
+        pressed_str.push_str(" ↑");
+        1
+    } else {
+        0
+    };
+
+    if held.is_held(Action::Brake) {
+        *pressed_str = String::from("Braking");
+        Some(Boost::Brake)
+    } else if turn != 0 || thrust != 0 {
+        Some(Boost::Thrust { turn, thrust })
+    } else {
+        None
+    }
+}
+
+// Take the next step's boosts from a replay, applying any resizes recorded before it.
+// Returns None once the replay is exhausted.
+fn next_replay_step<I, B>(entries: &mut I, console: &mut Console<B>) -> Option<Vec<(ParticleId, Boost)>>
+where
+    I: Iterator<Item = ReplayEntry>,
+    B: RenderBackend,
+{
+    for entry in entries {
+        match entry {
+            ReplayEntry::Resize(w, h) => console.resize(w, h),
+            ReplayEntry::Step(boosts) => return Some(boosts),
         }
     }
-    disable_raw_mode().expect("Failed to disable raw mode");
-    execute!(stdout, PopKeyboardEnhancementFlags)
-        .expect("Failed to unset set keyboard enhancement flags");
+    None
 }
@@ -1,5 +1,9 @@
+use crate::force::FORCE_SCALE;
 use crate::spatial::{Coordinate, ConsoleCell};
+use crate::sprite::{Sprite, SpriteId, SpriteRegistry};
 use crossterm::style::Color;
+use serde::Deserialize;
+use std::borrow::Cow;
 use std::cmp::max;
 use std::fmt::{Display, Formatter};
 use std::ops::{AddAssign};
@@ -7,42 +11,86 @@ use std::ops::{AddAssign};
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub struct ParticleId(pub u64);
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Ord, PartialOrd, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ParticleType {
     Rocket,
     FuelCell,
+    Debris,
+    // Purely visual, such as an explosion; collides with nothing unless configured to.
+    Effect,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ParticleColors {
     pub foreground: Color,
     pub background: Color,
 }
 
-#[derive(Clone, Debug)]
-pub struct Sprite {
-    // Relative cells to the anchor along with the character and its foreground color.
-    pub cells: Vec<(ConsoleCell, char, Color)>,
-    // The anchor cell; must be one of the entries in `cells`.
-    pub anchor: ConsoleCell,
-}
-
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Boost {
     Brake,
     Coordinate(Coordinate),
+    // For rockets steered by heading: rotate by `turn` heading steps (positive is
+    // clockwise), then thrust along the heading at `thrust` subpixels per step per step,
+    // burning `thrust` fuel.
+    Thrust { turn: i8, thrust: u8 },
+}
+
+// How a rocket is steered: thrusting along the x and y axes directly, or rotating and
+// thrusting along its heading, like in Asteroids.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlModel {
+    #[default]
+    Axes,
+    Heading,
+}
+
+// Number of distinct headings in a full turn; heading 0 points right, and they go round
+// clockwise on screen.
+pub const HEADINGS: u8 = 32;
+// The heading pointing straight up, which rockets start with.
+const HEADING_UP: u8 = HEADINGS * 3 / 4;
+
+// cos(k · 360° / HEADINGS) · 64 for the first quarter turn, rounded. Integers rather than
+// floating point trigonometry so that replays behave the same on every machine.
+const QUARTER_TURN: [i32; 9] = [64, 63, 59, 53, 45, 36, 24, 12, 0];
+
+// The direction of a heading, 64 long.
+fn heading_vector(heading: u8) -> Coordinate {
+    let step = (heading % (HEADINGS / 4)) as usize;
+    let v = Coordinate::new(QUARTER_TURN[step], QUARTER_TURN[8 - step]);
+    // Each quarter turn clockwise maps (x, y) to (-y, x), as y grows downwards.
+    match heading / (HEADINGS / 4) {
+        0 => v,
+        1 => Coordinate::new(-v.y, v.x),
+        2 => Coordinate::new(-v.x, -v.y),
+        _ => Coordinate::new(v.y, -v.x),
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct Particle {
     pub uid: ParticleId,
     pub position: Coordinate,   // In subpixel coordinates
+    pub prev_position: Coordinate, // Position before the last update, for swept collisions
     pub velocity: Coordinate,   // In subpixel coordinates per frame
     pub acceleration: Coordinate,
     pub color: Color,
     pub kind: ParticleType,
     pub fuel: u16,               // Remaining fuel units (0..=255)
     pub velocity_cap: Coordinate,
+    pub mass: Option<u16>,       // None for particles that cannot be pushed around
+    pub force_carry: Coordinate, // Field acceleration not yet whole subpixels, see `force`
+    pub control: ControlModel,
+    pub heading: u8,             // 0..HEADINGS, for rockets steered by heading
+    pub sprite: Option<SpriteId>, // None for the sprite of its type
+    pub animation: Option<SpriteId>, // Played in place of its sprite, see `play`
+    pub animation_ticks: u32,    // Ticks since its current sprite or animation started
+    pub thrust: Coordinate,      // Push of the boost in the last update, zero when coasting
+    pub age: u32,                // Updates since it was spawned
+    pub lifetime: Option<u32>,   // Age at which the scene despawns it; None to live forever
 }
 
 impl Display for Particle {
@@ -75,15 +123,27 @@ impl Particle {
         kind: ParticleType,
         velocity_cap: Coordinate,
     ) -> Self {
+        let position = position.unwrap_or_default();
         Self {
             uid: ParticleId(0), // will be set when added to the scene
-            position: position.unwrap_or_default(),
+            position,
+            prev_position: position,
             velocity: velocity.unwrap_or_default(),
             acceleration: acceleration.unwrap_or_default(),
             color: Color::White,
             kind,
             fuel: 510,
             velocity_cap,
+            mass: None,
+            force_carry: Coordinate::default(),
+            control: ControlModel::Axes,
+            heading: HEADING_UP,
+            sprite: None,
+            animation: None,
+            animation_ticks: 0,
+            thrust: Coordinate::default(),
+            age: 0,
+            lifetime: None,
         }
     }
 
@@ -101,18 +161,33 @@ impl Particle {
                 foreground: Color::DarkBlue,
                 background: Color::Yellow,
             },
-            ParticleType::Rocket => ParticleColors {
+            ParticleType::Debris => ParticleColors {
+                foreground: Color::Grey,
+                background: Color::Black,
+            },
+            ParticleType::Rocket | ParticleType::Effect => ParticleColors {
                 foreground: self.color,
                 background: Color::Black,
             },
         }
     }
 
-    pub fn update(&mut self, bounds: (i32, i32, u16, u16), boost: Option<Boost>) {
-        let (console_width, console_height, cell_width, cell_height) = bounds;
+    // Apply the boost and the force fields' acceleration (in 1/FORCE_SCALE subpixels per
+    // step per step) and move one step. The playfield's edges are the scene's business,
+    // see `boundary::apply`.
+    pub fn update(&mut self, boost: Option<Boost>, force: Coordinate) {
+        self.prev_position = self.position;
+        self.age = self.age.saturating_add(1);
+        self.animation_ticks = self.animation_ticks.saturating_add(1);
+
+        // Turning takes no fuel.
+        if let Some(Boost::Thrust { turn, .. }) = boost {
+            self.heading = (self.heading as i32 + turn as i32).rem_euclid(HEADINGS as i32) as u8;
+        }
 
         // 1) Handle boost: apply as first step, or reset to 0,0 if none.
         //    If out of fuel, ignore any boost (treated as None).
+        let mut thrust_force = Coordinate::new(0, 0);
         if self.fuel == 0 {
             self.acceleration = Coordinate::new(0, 0);
         } else {
@@ -125,6 +200,16 @@ impl Particle {
                     // Apply provided acceleration vector for this frame
                     self.acceleration += Coordinate::new(delta.x, delta.y);
                 }
+                Some(Boost::Thrust { thrust, .. }) => {
+                    // Thrust goes through the force carry below, so that headings between
+                    // the axes keep their direction however weak the thrust.
+                    self.acceleration = Coordinate::new(0, 0);
+                    let thrust = thrust.min(self.fuel.min(u8::MAX as u16) as u8);
+                    let unit = heading_vector(self.heading);
+                    let scale = thrust as i32 * FORCE_SCALE / 64;
+                    thrust_force = Coordinate::new(unit.x * scale, unit.y * scale);
+                    self.fuel -= thrust as u16;
+                }
                 None => {
                     // No boost provided: reset acceleration
                     self.acceleration = Coordinate::new(0, 0);
@@ -132,17 +217,29 @@ impl Particle {
             }
         }
 
+        self.thrust = if thrust_force != Coordinate::new(0, 0) {
+            thrust_force
+        } else {
+            self.acceleration
+        };
+
         // 2) Consume fuel (once per frame when accelerating) and apply acceleration to velocity
         if self.acceleration.x != 0 || self.acceleration.y != 0 {
             self.fuel = self.fuel.saturating_sub(1);
         }
         self.velocity += self.acceleration;
 
-        // 3) Clamp velocity to the cap per axis
+        // 3) Apply the fields' acceleration, carrying over what is not yet a whole subpixel
+        self.force_carry += force;
+        self.force_carry += thrust_force;
+        self.velocity += Coordinate::new(self.force_carry.x / FORCE_SCALE, self.force_carry.y / FORCE_SCALE);
+        self.force_carry = Coordinate::new(self.force_carry.x % FORCE_SCALE, self.force_carry.y % FORCE_SCALE);
+
+        // 4) Clamp velocity to the cap per axis
         self.velocity.x = self.velocity.x.clamp(-self.velocity_cap.x, self.velocity_cap.x);
         self.velocity.y = self.velocity.y.clamp(-self.velocity_cap.y, self.velocity_cap.y);
 
-        // 4) Integrate position with half-velocity for smoother motion
+        // 5) Integrate position with half-velocity for smoother motion
         //    Fuel cells move slower by integrating with a larger divisor.
         let divisor = match self.kind {
             ParticleType::FuelCell => 4,
@@ -150,45 +247,68 @@ impl Particle {
         };
         self.position
             .add(&Coordinate::new(self.velocity.x / divisor, self.velocity.y / divisor));
+    }
 
-        // 5) Bounce off borders
-        let as_cell = self.position.to_cell();
-        if as_cell.y >= cell_height - 1 {
-            self.position.y = console_height - (self.position.y - console_height).abs();
-            self.velocity.y = self.velocity.y * -1;
-        }
-        if self.position.y <= 1 {
-            self.position.y = self.position.y.abs();
-            self.velocity.y = self.velocity.y * -1;
-        }
-        if as_cell.x >= (cell_width - 1) {
-            self.position.x = console_width - (self.position.x - console_width).abs();
-            self.velocity.x = self.velocity.x * -1;
+    // The current frame of the particle's animation or sprite for the way it faces, a '?'
+    // if it has neither, or None for a blank animation frame.
+    pub fn get_sprite<'a>(&self, sprites: &'a SpriteRegistry) -> Option<Cow<'a, Sprite>> {
+        match self.animation.or_else(|| sprites.id_for(self)) {
+            Some(id) => sprites
+                .frame(id, self.direction(), self.animation_ticks)
+                .map(Cow::Borrowed),
+            None => {
+                let anchor = ConsoleCell::new(0, 0);
+                Some(Cow::Owned(Sprite {
+                    anchor,
+                    cells: vec![(anchor, '?', None)],
+                }))
+            }
         }
-        if as_cell.x <= 1 {
-            self.position.x = self.position.x.abs();
-            self.velocity.x = self.velocity.x * -1;
+    }
+
+    // The particle, set to be despawned by the scene after `ticks` more updates.
+    #[allow(dead_code)]
+    pub fn with_lifetime(mut self, ticks: u32) -> Self {
+        self.lifetime = Some(self.age.saturating_add(ticks));
+        self
+    }
+
+    // Whether the particle has reached the end of its lifetime.
+    pub fn expired(&self) -> bool {
+        self.lifetime.is_some_and(|lifetime| self.age >= lifetime)
+    }
+
+    // Play an animation in place of the particle's sprite, from its first frame, unless
+    // it is playing already.
+    pub fn play(&mut self, animation: SpriteId) {
+        if self.animation != Some(animation) {
+            self.animation = Some(animation);
+            self.animation_ticks = 0;
         }
     }
 
-    pub fn get_particle_char(&self) -> Sprite {
-        let ch = match self.kind {
-            ParticleType::Rocket => self.get_rocket_char(),
-            ParticleType::FuelCell => 'F',
-        };
-        let anchor = ConsoleCell::new(0, 0);
-        Sprite {
-            anchor,
-            cells: vec![(anchor, ch, self.get_colors().foreground)],
+    // Go back to the particle's own sprite if `animation` is what it is playing.
+    pub fn stop(&mut self, animation: SpriteId) {
+        if self.animation == Some(animation) {
+            self.animation = None;
+            self.animation_ticks = 0;
         }
     }
 
-    fn get_rocket_char(&self) -> char {
+    // Which of the 8 compass points the particle faces, clockwise from east (0) with y
+    // pointing down, so 2 is south and 6 north. Rockets steered by heading face their
+    // heading; everything else faces the way it moves, and None when standing still.
+    pub fn direction(&self) -> Option<u8> {
+        if self.control == ControlModel::Heading {
+            // Round the heading to the nearest of the 8 directions.
+            let per_direction = HEADINGS / 8;
+            return Some((self.heading + per_direction / 2) / per_direction % 8);
+        }
         let vx = self.velocity.x as f32;
         let vy = self.velocity.y as f32;
 
         if vx == 0.0 && vy == 0.0 {
-            return '•';
+            return None;
         }
 
         // Angle from - PI..PI, convert to 0..2PI and quantize to 8 sectors
@@ -198,19 +318,7 @@ impl Particle {
         }
 
         // Round to nearest sector (PI/4 each)
-        let sector =
-            ((ang + std::f32::consts::PI / 8.0) / (std::f32::consts::PI / 4.0)).floor() as i32 % 8;
-
-        match sector {
-            0 => '→', // East
-            1 => '↘', // South-East
-            2 => '↓', // South
-            3 => '↙', // North-West
-            4 => '←', // West
-            5 => '↖', // South-West
-            6 => '↑', // North
-            _ => '↗', // North-East
-        }
+        Some(((ang + std::f32::consts::PI / 8.0) / (std::f32::consts::PI / 4.0)).floor() as u8 % 8)
     }
 }
 
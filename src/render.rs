@@ -0,0 +1,148 @@
+// Render backends: where the console's frames actually end up.
+use crossterm::QueueableCommand;
+use crossterm::cursor::{Hide, MoveTo};
+use crossterm::style::{Colors, SetColors};
+use crossterm::terminal::{Clear, ClearType};
+use std::io::{Stdout, Write, stdout};
+
+use crate::console::{DEFAULT_BACKGROUND_COLOR, DEFAULT_FOREGROUND_COLOR};
+use crate::particle::ParticleColors;
+use crate::spatial::ConsoleCell;
+
+pub const DEFAULT_COLORS: ParticleColors = ParticleColors {
+    foreground: DEFAULT_FOREGROUND_COLOR,
+    background: DEFAULT_BACKGROUND_COLOR,
+};
+
+// A minimal drawing surface. Cells are written with whatever colors were last set.
+pub trait RenderBackend {
+    fn put_cell(&mut self, cell: ConsoleCell, ch: char);
+    fn set_colors(&mut self, colors: ParticleColors);
+    fn clear(&mut self);
+    fn flush(&mut self);
+
+    // Called when the drawable area changes size; most backends need not care.
+    fn resize(&mut self, _width: u16, _height: u16) {}
+
+    // Writes a string left-to-right starting at `cell`.
+    fn put_str(&mut self, cell: ConsoleCell, s: &str) {
+        for (i, ch) in s.chars().enumerate() {
+            self.put_cell(ConsoleCell::new(cell.x + i as u16, cell.y), ch);
+        }
+    }
+}
+
+// Draws to the real terminal through crossterm.
+pub struct CrosstermBackend {
+    stdout: Stdout,
+}
+
+impl CrosstermBackend {
+    pub fn new() -> Self {
+        Self { stdout: stdout() }
+    }
+}
+
+impl Default for CrosstermBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderBackend for CrosstermBackend {
+    fn put_cell(&mut self, cell: ConsoleCell, ch: char) {
+        self.stdout.queue(MoveTo(cell.x, cell.y)).unwrap();
+        let mut buf = [0u8; 4];
+        self.stdout.write_all(ch.encode_utf8(&mut buf).as_bytes()).unwrap();
+    }
+
+    fn set_colors(&mut self, colors: ParticleColors) {
+        self.stdout
+            .queue(SetColors(Colors::new(colors.foreground, colors.background)))
+            .unwrap();
+    }
+
+    fn clear(&mut self) {
+        self.stdout.queue(Clear(ClearType::All)).unwrap();
+    }
+
+    fn flush(&mut self) {
+        self.stdout.queue(Hide).unwrap();
+        self.stdout.flush().unwrap();
+    }
+
+    // One cursor move for the whole string instead of one per character.
+    fn put_str(&mut self, cell: ConsoleCell, s: &str) {
+        self.stdout.queue(MoveTo(cell.x, cell.y)).unwrap();
+        self.stdout.write_all(s.as_bytes()).unwrap();
+    }
+}
+
+// Keeps the drawn frame in memory so it can be inspected without a terminal.
+#[allow(dead_code)] // Not driven by the interactive binary itself.
+pub struct BufferBackend {
+    pub width: u16,
+    pub height: u16,
+    cells: Vec<(char, ParticleColors)>,
+    colors: ParticleColors,
+    // Cells written so far, to check that unchanged frames write nothing.
+    writes: usize,
+}
+
+#[allow(dead_code)]
+impl BufferBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![(' ', DEFAULT_COLORS); width as usize * height as usize],
+            colors: DEFAULT_COLORS,
+            writes: 0,
+        }
+    }
+
+    // How many cells have been written since the backend was created.
+    pub fn writes(&self) -> usize {
+        self.writes
+    }
+
+    // The character and colors at a cell, or None if out of bounds.
+    pub fn get(&self, cell: ConsoleCell) -> Option<(char, ParticleColors)> {
+        if cell.x < self.width && cell.y < self.height {
+            Some(self.cells[cell.y as usize * self.width as usize + cell.x as usize])
+        } else {
+            None
+        }
+    }
+
+    // The characters of one row, useful for asserting on whole lines.
+    pub fn row(&self, y: u16) -> String {
+        (0..self.width)
+            .filter_map(|x| self.get(ConsoleCell::new(x, y)).map(|(ch, _)| ch))
+            .collect()
+    }
+}
+
+impl RenderBackend for BufferBackend {
+    fn put_cell(&mut self, cell: ConsoleCell, ch: char) {
+        self.writes += 1;
+        // Writes past the edge are dropped, like a terminal clipping them.
+        if cell.x < self.width && cell.y < self.height {
+            self.cells[cell.y as usize * self.width as usize + cell.x as usize] = (ch, self.colors);
+        }
+    }
+
+    fn set_colors(&mut self, colors: ParticleColors) {
+        self.colors = colors;
+    }
+
+    fn clear(&mut self) {
+        self.cells.fill((' ', self.colors));
+    }
+
+    fn flush(&mut self) {}
+
+    fn resize(&mut self, width: u16, height: u16) {
+        *self = Self::new(width, height);
+    }
+}
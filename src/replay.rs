@@ -0,0 +1,367 @@
+// Replay files: the RNG seed, playfield size and settings plus every step's boosts,
+// enough to reproduce a session frame for frame.
+//
+// The format is plain text, one record per line:
+//
+//   book-replay 2
+//   seed 42
+//   size 80 24
+//   tick-rate 40
+//   debris 3
+//   depots 1
+//   motherships 0
+//   player-control heading
+//   settings 5c4fa1e2b9d03f87
+//   step 1:1,0
+//   step 1:B
+//   step
+//   step 1:T-1,1
+//   resize 100 30
+//
+// Each `step` lists the boosts given that step as `id:boost`, by the id of the particle
+// they went to: `B` for brake, `x,y` for an acceleration vector and `Tturn,thrust` for
+// turning and thrusting. A `resize` changes the playfield before the next step.
+// The `debris`, `depots`, `motherships` and `player-control` lines are optional and
+// default to none and axis control, as in files that predate them. `settings` is a hash
+// of the config and sprites the session ran with, see `settings_hash`; a replay only
+// plays back under the same ones.
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::particle::{Boost, ControlModel, ParticleId};
+use crate::spatial::Coordinate;
+use crate::spawn::Population;
+use crate::sprite::SpriteRegistry;
+
+const HEADER: &str = "book-replay 2";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayEntry {
+    Step(Vec<(ParticleId, Boost)>),
+    Resize(u16, u16),
+}
+
+// What a session starts from, as written at the top of its replay file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReplayHeader {
+    pub seed: u64,
+    pub width: u16,
+    pub height: u16,
+    pub tick_rate: u32,
+    pub population: Population,
+    pub player_control: ControlModel,
+    pub settings: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub entries: Vec<ReplayEntry>,
+}
+
+// A hash of the settings that steer the simulation besides those in the header: the
+// collision matrix, boundaries, force fields and sprite footprints. Key bindings and
+// the look of sprites are left out, since replays play back the same without them.
+pub fn settings_hash(config: &Config, sprites: &SpriteRegistry) -> u64 {
+    let mut hasher = Fnv1a::default();
+    config.collisions.hash(&mut hasher);
+    config.boundaries.hash(&mut hasher);
+    config.fields.hash(&mut hasher);
+    sprites.hash(&mut hasher);
+    hasher.finish()
+}
+
+// The FNV-1a hash, which unlike the standard library's hasher is the same in every
+// build, so that a hash written to a replay file can be checked by another one.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Writes a replay file as the session goes, so a crash loses at most the buffered tail.
+pub struct ReplayRecorder {
+    out: BufWriter<File>,
+}
+
+impl ReplayRecorder {
+    pub fn create<P: AsRef<Path>>(path: P, header: &ReplayHeader) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "seed {}", header.seed)?;
+        writeln!(out, "size {} {}", header.width, header.height)?;
+        writeln!(out, "tick-rate {}", header.tick_rate)?;
+        writeln!(out, "debris {}", header.population.debris)?;
+        writeln!(out, "depots {}", header.population.depots)?;
+        writeln!(out, "motherships {}", header.population.motherships)?;
+        if header.player_control == ControlModel::Heading {
+            writeln!(out, "player-control heading")?;
+        }
+        writeln!(out, "settings {:016x}", header.settings)?;
+        Ok(Self { out })
+    }
+
+    pub fn record_step(&mut self, boosts: &[(ParticleId, Boost)]) -> io::Result<()> {
+        let mut line = String::from("step");
+        for (id, boost) in boosts {
+            line.push_str(&format!(" {}:{}", id.0, format_boost(boost)));
+        }
+        writeln!(self.out, "{}", line)
+    }
+
+    pub fn record_resize(&mut self, width: u16, height: u16) -> io::Result<()> {
+        writeln!(self.out, "resize {} {}", width, height)
+    }
+}
+
+impl Drop for ReplayRecorder {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+impl Replay {
+    // Load a replay, refusing one recorded under settings other than `settings`, see
+    // `settings_hash`.
+    pub fn load<P: AsRef<Path>>(path: P, settings: u64) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut lines = BufReader::new(file).lines().enumerate();
+
+        let mut next_line = || -> Result<Option<(usize, String)>, String> {
+            match lines.next() {
+                Some((i, line)) => line
+                    .map(|l| Some((i + 1, l)))
+                    .map_err(|e| format!("{}: {}", path.display(), e)),
+                None => Ok(None),
+            }
+        };
+        let error = |line_no: usize, message: &str| {
+            format!("{}:{}: {}", path.display(), line_no, message)
+        };
+
+        match next_line()? {
+            Some((_, line)) if line.trim() == HEADER => (),
+            _ => return Err(format!("{}: not a replay file", path.display())),
+        }
+
+        let mut seed = None;
+        let mut size = None;
+        let mut tick_rate = None;
+        let mut population = Population::default();
+        let mut player_control = ControlModel::Axes;
+        let mut recorded_settings = None;
+        let mut entries = Vec::new();
+
+        while let Some((line_no, line)) = next_line()? {
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let args: Vec<&str> = words.collect();
+            match (keyword, args.as_slice()) {
+                ("seed", [s]) => seed = Some(s.parse().map_err(|_| error(line_no, "bad seed"))?),
+                ("size", [w, h]) | ("resize", [w, h]) => {
+                    let parsed: (u16, u16) = (
+                        w.parse().map_err(|_| error(line_no, "bad width"))?,
+                        h.parse().map_err(|_| error(line_no, "bad height"))?,
+                    );
+                    // The same minimum as `--width` and `--height`.
+                    if parsed.0 < 3 || parsed.1 < 3 {
+                        return Err(error(line_no, "width and height must be at least 3"));
+                    }
+                    if keyword == "size" {
+                        size = Some(parsed);
+                    } else {
+                        entries.push(ReplayEntry::Resize(parsed.0, parsed.1));
+                    }
+                }
+                ("tick-rate", [r]) => {
+                    let rate: u32 = r.parse().map_err(|_| error(line_no, "bad tick rate"))?;
+                    if rate == 0 {
+                        return Err(error(line_no, "tick rate must be greater than zero"));
+                    }
+                    tick_rate = Some(rate);
+                }
+                ("debris", [n]) | ("depots", [n]) | ("motherships", [n]) => {
+                    let count = n.parse().map_err(|_| error(line_no, "bad count"))?;
+                    match keyword {
+                        "debris" => population.debris = count,
+                        "depots" => population.depots = count,
+                        _ => population.motherships = count,
+                    }
+                }
+                ("player-control", ["axes"]) => player_control = ControlModel::Axes,
+                ("player-control", ["heading"]) => player_control = ControlModel::Heading,
+                ("settings", [hash]) => {
+                    let hash = u64::from_str_radix(hash, 16)
+                        .map_err(|_| error(line_no, "bad settings hash"))?;
+                    if hash != settings {
+                        return Err(error(
+                            line_no,
+                            "recorded with other collision, boundary, force field or sprite settings",
+                        ));
+                    }
+                    recorded_settings = Some(hash);
+                }
+                ("step", tokens) => {
+                    let mut boosts = Vec::new();
+                    for token in tokens {
+                        let bad = || error(line_no, "bad boost");
+                        let (id, boost) = token.split_once(':').ok_or_else(bad)?;
+                        let id = ParticleId(id.parse().map_err(|_| bad())?);
+                        boosts.push((id, parse_boost(boost).ok_or_else(bad)?));
+                    }
+                    entries.push(ReplayEntry::Step(boosts));
+                }
+                _ => return Err(error(line_no, "unrecognised record")),
+            }
+        }
+
+        let missing = |what: &str| format!("{}: missing {}", path.display(), what);
+        let (width, height) = size.ok_or_else(|| missing("size"))?;
+        Ok(Self {
+            header: ReplayHeader {
+                seed: seed.ok_or_else(|| missing("seed"))?,
+                width,
+                height,
+                tick_rate: tick_rate.ok_or_else(|| missing("tick-rate"))?,
+                population,
+                player_control,
+                settings: recorded_settings.ok_or_else(|| missing("settings"))?,
+            },
+            entries,
+        })
+    }
+
+    // Number of simulation steps in the replay.
+    pub fn step_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e, ReplayEntry::Step(_)))
+            .count()
+    }
+}
+
+fn format_boost(boost: &Boost) -> String {
+    match boost {
+        Boost::Brake => String::from("B"),
+        Boost::Coordinate(c) => format!("{},{}", c.x, c.y),
+        Boost::Thrust { turn, thrust } => format!("T{},{}", turn, thrust),
+    }
+}
+
+fn parse_boost(token: &str) -> Option<Boost> {
+    match token {
+        "B" => Some(Boost::Brake),
+        _ if token.starts_with('T') => {
+            let (turn, thrust) = token[1..].split_once(',')?;
+            Some(Boost::Thrust {
+                turn: turn.parse().ok()?,
+                thrust: thrust.parse().ok()?,
+            })
+        }
+        _ => {
+            let (x, y) = token.split_once(',')?;
+            Some(Boost::Coordinate(Coordinate::new(
+                x.parse().ok()?,
+                y.parse().ok()?,
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file of its own in the temporary directory, removed again when dropped.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("book-{}-{}", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn header() -> ReplayHeader {
+        ReplayHeader {
+            seed: 42,
+            width: 80,
+            height: 24,
+            tick_rate: 40,
+            population: Population {
+                debris: 3,
+                depots: 1,
+                motherships: 2,
+            },
+            player_control: ControlModel::Heading,
+            settings: settings_hash(&Config::default(), &SpriteRegistry::default()),
+        }
+    }
+
+    #[test]
+    fn recorded_steps_and_resizes_read_back_unchanged() {
+        let file = TempFile::new("round-trip.replay");
+        let entries = vec![
+            ReplayEntry::Step(vec![(ParticleId(1), Boost::Coordinate(Coordinate::new(-1, 1)))]),
+            ReplayEntry::Step(vec![]),
+            ReplayEntry::Resize(100, 30),
+            ReplayEntry::Step(vec![
+                (ParticleId(1), Boost::Thrust { turn: -1, thrust: 1 }),
+                (ParticleId(7), Boost::Brake),
+            ]),
+            ReplayEntry::Resize(3, 3),
+        ];
+        {
+            let mut recorder = ReplayRecorder::create(&file.0, &header()).unwrap();
+            for entry in entries.iter() {
+                match entry {
+                    ReplayEntry::Step(boosts) => recorder.record_step(boosts).unwrap(),
+                    ReplayEntry::Resize(w, h) => recorder.record_resize(*w, *h).unwrap(),
+                }
+            }
+        }
+
+        let replay = Replay::load(&file.0, header().settings).unwrap();
+        assert_eq!(replay.header, header());
+        assert_eq!(replay.entries, entries);
+        assert_eq!(replay.step_count(), 3);
+    }
+
+    #[test]
+    fn other_settings_are_refused() {
+        let file = TempFile::new("settings.replay");
+        ReplayRecorder::create(&file.0, &header()).unwrap();
+
+        let mut config = Config::default();
+        config.boundaries.default = crate::boundary::BoundaryMode::Wrap;
+        let settings = settings_hash(&config, &SpriteRegistry::default());
+        assert_ne!(settings, header().settings);
+        let error = Replay::load(&file.0, settings).unwrap_err();
+        assert!(error.contains("recorded with other"), "{}", error);
+    }
+}
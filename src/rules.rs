@@ -0,0 +1,93 @@
+// Game rules: react to a step's events by queueing scene commands.
+use crate::collision::bounce_impulses;
+use crate::commands::SceneCommands;
+use crate::emitter::Emitter;
+use crate::game_events::GameEvent;
+use crate::particle::{Particle, ParticleType};
+use crate::scene::Scene;
+use crate::spatial::{Coordinate, SUBPIXEL_SCALE};
+
+// Fuel capacity a rocket is refilled to when it picks up a fuel cell.
+pub const FULL_FUEL: u16 = 510;
+
+// Fuel below which a rocket's low fuel warning shows.
+pub const LOW_FUEL: u16 = FULL_FUEL / 5;
+
+// A rule sees every event of a step together with the scene as it was when the events
+// were produced; changes only take effect through the commands it queues.
+pub type Rule = fn(&GameEvent, &Scene, &mut SceneCommands);
+
+// The rules every scene starts with.
+pub fn default_rules() -> Vec<Rule> {
+    vec![refuel, pickup, bounce, explode]
+}
+
+// A rocket that touches a fuel cell is refilled and the fuel cell is used up.
+pub fn refuel(event: &GameEvent, _scene: &Scene, commands: &mut SceneCommands) {
+    if let GameEvent::Refuel { rocket, fuel_cell } = event {
+        commands.set_fuel(*rocket, FULL_FUEL);
+        commands.despawn(*fuel_cell);
+    }
+}
+
+// A picked up particle is used up.
+pub fn pickup(event: &GameEvent, _scene: &Scene, commands: &mut SceneCommands) {
+    if let GameEvent::Pickup { item, .. } = event {
+        commands.despawn(*item);
+    }
+}
+
+// Particles whose pair has a restitution in the collision matrix bounce off each other.
+pub fn bounce(event: &GameEvent, scene: &Scene, commands: &mut SceneCommands) {
+    let GameEvent::Impact { a, b, .. } = event else {
+        return;
+    };
+    let (Some(p), Some(q)) = (scene.get(*a), scene.get(*b)) else {
+        return;
+    };
+    let Some(restitution) = scene.collision_matrix().restitution(p.kind, q.kind) else {
+        return;
+    };
+    let Some(bounce) = bounce_impulses(p, q, restitution) else {
+        return;
+    };
+    let (on_a, on_b) = bounce.impulses;
+    commands.apply_impulse(*a, on_a);
+    commands.apply_impulse(*b, on_b);
+    if let Some((at_a, at_b)) = bounce.rewind {
+        commands.set_position(*a, at_a);
+        commands.set_position(*b, at_b);
+    }
+}
+
+// Impacts that do not bounce, such as two rockets crashing, set off an explosion where
+// they happen: a burst of sparks, and the `explosion` sprite if there is one. Particles
+// passing through each other stay in contact for several steps; only the first explodes.
+pub fn explode(event: &GameEvent, scene: &Scene, commands: &mut SceneCommands) {
+    let GameEvent::Impact { a, b, cell, .. } = event else {
+        return;
+    };
+    if scene.were_touching(*a, *b) {
+        return;
+    }
+    let (Some(p), Some(q)) = (scene.get(*a), scene.get(*b)) else {
+        return;
+    };
+    if scene.collision_matrix().restitution(p.kind, q.kind).is_some() {
+        return;
+    }
+    let position = Coordinate::new(cell.x as i32 * SUBPIXEL_SCALE, cell.y as i32 * SUBPIXEL_SCALE);
+    commands.burst(position, Emitter::explosion());
+    let Some(sprite) = scene.sprites().id("explosion") else {
+        return;
+    };
+    let mut explosion = Particle::new(
+        Some(position),
+        None,
+        None,
+        ParticleType::Effect,
+        Coordinate::new(0, 0),
+    );
+    explosion.sprite = Some(sprite);
+    commands.spawn(explosion);
+}
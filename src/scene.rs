@@ -1,43 +1,343 @@
-use crate::particle::{Particle, ParticleColors, ParticleId};
-use crate::spatial::ConsoleCell;
-use crate::collision::Collision;
+use std::collections::{HashMap, HashSet};
+
+use crate::boundary::{self, Boundaries, BoundaryMode};
+use crate::particle::{Boost, Particle, ParticleColors, ParticleId, ParticleType};
+use crate::spatial::{ConsoleCell, Coordinate};
+use crate::collision::{Collision, CollisionMatrix, detect_collisions, div_round};
+use crate::commands::{SceneCommand, SceneCommands};
+use crate::emitter::{Emitter, Emitters};
+use crate::force::{self, ForceField};
+use crate::game_events::GameEvent;
+use crate::sprite::SpriteRegistry;
+use crate::rules::{LOW_FUEL, Rule, default_rules};
 
 #[derive(Clone)]
 pub struct Scene {
-    pub particles: Vec<Particle>,
+    // Particles in scene order, which is the order they are updated in.
+    particles: Vec<Particle>,
+    // Position of each particle in `particles`, for constant-time lookup by id.
+    index: HashMap<ParticleId, usize>,
     pub next_id: u64,
+    // Commands waiting for the end of the next step.
+    commands: SceneCommands,
+    rules: Vec<Rule>,
+    // What each particle type does at the edge of the playfield.
+    boundaries: Boundaries,
+    // Sprite definitions, for drawing particles and for their collision footprints.
+    sprites: SpriteRegistry,
+    // Force fields acting on every particle.
+    fields: Vec<ForceField>,
+    // Which particle types collide with each other, and how.
+    collision_matrix: CollisionMatrix,
+    // Emitters attached to particles and the cosmetic sparks they emitted.
+    emitters: Emitters,
+    // Pairs of particles that collided in the last step, as their participants.
+    touching: HashSet<(ParticleId, ParticleId)>,
+    // Total fuel burnt by particles since the scene was created.
+    fuel_consumed: u64,
 }
 
+// What to draw for a scene; purely visual, collisions are found separately.
 pub struct RenderableScene {
     pub cells: Vec<(ConsoleCell, char, ParticleColors)>,
-    pub collisions: Vec<Collision>,
 }
 
 impl Scene {
     pub fn new(particles: Vec<Particle>) -> Self {
-        Self { particles, next_id: 1 }
+        let mut scene = Self {
+            particles: Vec::with_capacity(particles.len()),
+            index: HashMap::new(),
+            next_id: 1,
+            commands: SceneCommands::default(),
+            rules: default_rules(),
+            collision_matrix: CollisionMatrix::default(),
+            boundaries: Boundaries::default(),
+            fields: Vec::new(),
+            sprites: SpriteRegistry::default(),
+            emitters: Emitters::default(),
+            touching: HashSet::new(),
+            fuel_consumed: 0,
+        };
+        for particle in particles {
+            scene.add_particle(particle);
+        }
+        scene
     }
 
-    pub fn add_particle(&mut self, mut particle: Particle) {
+    // Adds a particle at the end of the scene order, assigning it an id if it has none.
+    pub fn add_particle(&mut self, mut particle: Particle) -> ParticleId {
         if particle.uid.0 == 0 {
             particle.uid = ParticleId(self.next_id);
             self.next_id += 1;
         } else if particle.uid.0 >= self.next_id {
             self.next_id = particle.uid.0 + 1;
         }
+        self.index.insert(particle.uid, self.particles.len());
         self.particles.push(particle);
+        particle.uid
+    }
+
+    // Removes a particle, keeping the order of the others. Returns None if it is not
+    // in the scene, so removing the same particle twice is harmless.
+    pub fn remove_particle(&mut self, id: ParticleId) -> Option<Particle> {
+        let idx = self.index.remove(&id)?;
+        let removed = self.particles.remove(idx);
+        for p in self.particles[idx..].iter() {
+            self.index.insert(p.uid, self.index[&p.uid] - 1);
+        }
+        Some(removed)
+    }
+
+    // Queue commands to be applied at the end of the next step, along with the rules'.
+    #[allow(dead_code)]
+    pub fn commands(&mut self) -> &mut SceneCommands {
+        &mut self.commands
+    }
+
+    // Add a game rule; it runs after the existing ones.
+    #[allow(dead_code)]
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    // Whether two particles were already in contact in the step before this one, so that
+    // rules can tell a new collision from one that carries on.
+    pub fn were_touching(&self, a: ParticleId, b: ParticleId) -> bool {
+        self.touching.contains(&(a, b)) || self.touching.contains(&(b, a))
+    }
+
+    pub fn collision_matrix(&self) -> &CollisionMatrix {
+        &self.collision_matrix
+    }
+
+    pub fn set_collision_matrix(&mut self, matrix: CollisionMatrix) {
+        self.collision_matrix = matrix;
+    }
+
+    pub fn set_boundaries(&mut self, boundaries: Boundaries) {
+        self.boundaries = boundaries;
+    }
+
+    pub fn sprites(&self) -> &SpriteRegistry {
+        &self.sprites
+    }
+
+    pub fn set_sprites(&mut self, sprites: SpriteRegistry) {
+        self.sprites = sprites;
+    }
+
+    // Place a force field in the scene; it acts from the next step on.
+    pub fn add_field(&mut self, field: ForceField) {
+        self.fields.push(field);
+    }
+
+    // Attach an emitter to a particle, to emit sparks for as long as it is in the scene.
+    pub fn attach_emitter(&mut self, host: ParticleId, emitter: Emitter) {
+        self.emitters.attach(host, emitter);
+    }
+
+    pub fn fuel_consumed(&self) -> u64 {
+        self.fuel_consumed
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn get(&self, id: ParticleId) -> Option<&Particle> {
+        self.index.get(&id).map(|&idx| &self.particles[idx])
+    }
+
+    pub fn get_mut(&mut self, id: ParticleId) -> Option<&mut Particle> {
+        self.index.get(&id).map(|&idx| &mut self.particles[idx])
+    }
+
+    // Runs one simulation step for a playfield of the given size (in cells):
+    // updates every particle with its boost and applies the playfield's boundaries,
+    // works out the resulting events, lets the
+    // rules react to them and finally applies all queued commands.
+    // Each boost goes to the particle with its id; particles without one get no boost,
+    // and boosts for particles no longer in the scene are dropped.
+    pub fn step(&mut self, cell_width: u16, cell_height: u16, boosts: &[(ParticleId, Boost)]) -> Vec<GameEvent> {
+        // 1) Update particles in-place, then deal with any that reached an edge or the end
+        //    of their lifetime, and with their animations
+        let mut events: Vec<GameEvent> = Vec::new();
+        // Particles on their way out, which take no part in collisions.
+        let mut leaving: HashSet<ParticleId> = HashSet::new();
+        for particle in self.particles.iter_mut() {
+            let b = boosts.iter().find(|(id, _)| *id == particle.uid).map(|&(_, boost)| boost);
+            let fuel_before = particle.fuel;
+            particle.update(b, force::total_acceleration(&self.fields, particle));
+            self.fuel_consumed += (fuel_before - particle.fuel) as u64;
+
+            let mode = self.boundaries.mode_for(particle.kind);
+            let extent = self.sprites.extent(self.sprites.id_for(particle));
+            let edges = boundary::apply(particle, mode, extent, cell_width, cell_height);
+            for edge in edges.iter() {
+                events.push(GameEvent::BorderHit { id: particle.uid, edge: *edge });
+            }
+            if mode == BoundaryMode::Despawn && !edges.is_empty() {
+                events.push(GameEvent::Despawned { id: particle.uid });
+                self.commands.despawn(particle.uid);
+                leaving.insert(particle.uid);
+                continue;
+            }
+            if particle.expired() {
+                events.push(GameEvent::Expired { id: particle.uid });
+                self.commands.despawn(particle.uid);
+                leaving.insert(particle.uid);
+                continue;
+            }
+
+            // Rockets running low on fuel blink their warning, if there is one.
+            if particle.kind == ParticleType::Rocket
+                && let Some(warning) = self.sprites.id("low_fuel")
+            {
+                if particle.fuel < LOW_FUEL {
+                    particle.play(warning);
+                } else {
+                    particle.stop(warning);
+                }
+            }
+            // One-shot animations that have played out end, or take their particle with them.
+            if let Some(id) = particle.animation.or_else(|| self.sprites.id_for(particle))
+                && let Some(despawn) = self.sprites.finished(id, particle.animation_ticks)
+            {
+                if despawn {
+                    events.push(GameEvent::Despawned { id: particle.uid });
+                    self.commands.despawn(particle.uid);
+                    leaving.insert(particle.uid);
+                } else {
+                    particle.stop(id);
+                }
+            }
+        }
+
+        // 2) Find collisions in the new state, among the particles that are staying
+        let mut collisions = detect_collisions(self);
+        collisions.retain(|collision| {
+            let (a, b) = collision.participants();
+            !leaving.contains(&a) && !leaving.contains(&b)
+        });
+        let touching = collisions.iter().map(Collision::participants).collect();
+
+        // 3) Each collision already knows its participants' roles; turn it into an event
+        events.extend(collisions.into_iter()
+            .map(|collision| match collision {
+                Collision::Refuel { rocket, fuel_cell, .. } => GameEvent::Refuel { rocket, fuel_cell },
+                Collision::Impact {
+                    a,
+                    b,
+                    cell,
+                    relative_velocity,
+                } => GameEvent::Impact {
+                    a,
+                    b,
+                    cell,
+                    relative_velocity,
+                },
+                Collision::Pickup { collector, item, .. } => GameEvent::Pickup { collector, item },
+            }));
+
+        // 4) Let the rules react, then apply everything queued at once
+        let mut commands = std::mem::take(&mut self.commands);
+        for event in events.iter() {
+            for rule in self.rules.iter() {
+                rule(event, self, &mut commands);
+            }
+        }
+        self.apply_commands(commands);
+        self.touching = touching;
+
+        // 5) Sparks, which nothing else sees, fly after the particles they came from
+        let (particles, index) = (&self.particles, &self.index);
+        self.emitters.step(|id| index.get(&id).map(|&idx| &particles[idx]));
+
+        events
     }
 
-    // Returns both renderable data and collisions for this scene.
+    // Apply and clear the queued commands, in a fixed order regardless of queue order:
+    // 1) changes to existing particles (fuel, velocity, impulses), in queue order;
+    // 2) despawns, so a particle changed and despawned in one step just disappears;
+    // 3) spawns, so new particles are never affected by the same step's commands, and
+    //    bursts of sparks.
+    fn apply_commands(&mut self, mut commands: SceneCommands) {
+        let commands = commands.take();
+        for command in commands.iter() {
+            match command {
+                SceneCommand::SetFuel(id, fuel) => {
+                    if let Some(p) = self.get_mut(*id) {
+                        p.fuel = *fuel;
+                    }
+                }
+                SceneCommand::SetVelocity(id, velocity) => {
+                    if let Some(p) = self.get_mut(*id) {
+                        p.velocity = *velocity;
+                    }
+                }
+                SceneCommand::SetPosition(id, position) => {
+                    if let Some(p) = self.get_mut(*id) {
+                        p.position = *position;
+                    }
+                }
+                SceneCommand::ApplyImpulse(id, impulse) => {
+                    if let Some(p) = self.get_mut(*id)
+                        && let Some(mass) = p.mass
+                    {
+                        let mass = mass.max(1) as i64;
+                        p.velocity += Coordinate::new(
+                            div_round(impulse.x as i64, mass) as i32,
+                            div_round(impulse.y as i64, mass) as i32,
+                        );
+                    }
+                }
+                SceneCommand::Spawn(_) | SceneCommand::Despawn(_) | SceneCommand::Burst(..) => {}
+            }
+        }
+        for command in commands.iter() {
+            if let SceneCommand::Despawn(id) = command {
+                self.remove_particle(*id);
+            }
+        }
+        for command in commands {
+            match command {
+                SceneCommand::Spawn(particle) => {
+                    self.add_particle(particle);
+                }
+                SceneCommand::Burst(position, emitter) => self.emitters.burst(position, &emitter),
+                _ => {}
+            }
+        }
+    }
+
+    // Move any particle that lies outside the interior of a playfield of the given
+    // size back inside it, whole sprite and all where there is room, see `boundary::apply`.
+    pub fn clamp_into(&mut self, cell_width: u16, cell_height: u16) {
+        for p in self.particles.iter_mut() {
+            let extent = self.sprites.extent(self.sprites.id_for(p));
+            boundary::clamp_inside(p, extent, cell_width, cell_height);
+        }
+    }
+
+    // Returns the cells to draw for this scene.
     pub fn get_renderable(&self, cell_width: u16, cell_height: u16) -> RenderableScene {
-        // Renderable particles (cell, char, colors)
+        // Renderable particles (cell, char, colors), drawn over the fields' map features
+        // and the sparks
         let mut cells_to_render: Vec<(ConsoleCell, char, ParticleColors)> = Vec::new();
+        let features = self.fields.iter().filter_map(ForceField::map_feature);
+        for (cell, ch, colors) in features.chain(self.emitters.renderable()) {
+            if cell.x >= 1 && cell.x < cell_width - 1 && cell.y >= 1 && cell.y < cell_height - 1 {
+                cells_to_render.push((cell, ch, colors));
+            }
+        }
         for p in self.particles.iter() {
             let base_cell = p.get_position().to_cell();
-            let sprite = p.get_particle_char(); // now returns Sprite
-            let bg = p.get_colors().background;
+            let Some(sprite) = p.get_sprite(&self.sprites) else {
+                continue;
+            };
+            let own = p.get_colors();
 
-            for (rel_cell, ch, fg) in sprite.cells {
+            for &(rel_cell, ch, fg) in sprite.cells.iter() {
                 // Place the sprite so that its anchor lands at the particle's base cell.
                 // abs = base + (rel - anchor)
                 let abs_x_i = base_cell.x as i32 + rel_cell.x as i32 - sprite.anchor.x as i32;
@@ -51,43 +351,111 @@ impl Scene {
                 {
                     let abs_cell = ConsoleCell::new(abs_x_i as u16, abs_y_i as u16);
                     let colors = ParticleColors {
-                        foreground: fg,
-                        background: bg,
+                        foreground: fg.unwrap_or(own.foreground),
+                        background: own.background,
                     };
                     cells_to_render.push((abs_cell, ch, colors));
                 }
             }
         }
 
-        // Collisions grouped by console cell
-        let mut collisions: Vec<Collision> = Vec::new();
-        let mut processed_cells: Vec<ConsoleCell> = Vec::new();
-
-        for i in 0..self.particles.len() {
-            let cell_i = self.particles[i].get_position().to_cell();
-            if processed_cells.contains(&cell_i) {
-                continue;
-            }
-            // Collect indices of all particles occupying the same cell
-            let mut group: Vec<usize> = vec![i];
-            for j in (i + 1)..self.particles.len() {
-                if self.particles[j].get_position().to_cell() == cell_i {
-                    group.push(j);
-                }
-            }
-            if group.len() > 1 {
-                // For now, every detected collision is considered a Refuel type.
-                let participant_ids = group.iter().map(|&idx| self.particles[idx].uid).collect();
-                collisions.push(Collision::Refuel {
-                    participants: participant_ids,
-                });
-            }
-            processed_cells.push(cell_i);
-        }
-
         RenderableScene {
             cells: cells_to_render,
-            collisions,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::SUBPIXEL_SCALE;
+
+    fn rocket_at(x: i32, y: i32) -> Particle {
+        Particle::new(
+            Some(Coordinate::new(x * SUBPIXEL_SCALE, y * SUBPIXEL_SCALE)),
+            None,
+            None,
+            ParticleType::Rocket,
+            Coordinate::new(200, 200),
+        )
+    }
+
+    #[test]
+    fn boosts_follow_ids_not_scene_order() {
+        let mut scene = Scene::new(vec![rocket_at(5, 5), rocket_at(10, 5)]);
+        let (first, second) = (scene.particles()[0].uid, scene.particles()[1].uid);
+        scene.remove_particle(first);
+        let push = Boost::Coordinate(Coordinate::new(1, 0));
+
+        scene.step(20, 10, &[(first, push)]);
+        assert_eq!(scene.get(second).unwrap().acceleration, Coordinate::new(0, 0));
+
+        scene.step(20, 10, &[(second, push)]);
+        assert_eq!(scene.get(second).unwrap().acceleration, Coordinate::new(1, 0));
+    }
+
+    #[test]
+    fn shrinking_keeps_whole_sprites_inside() {
+        let mut mothership = rocket_at(25, 12);
+        mothership.kind = ParticleType::Debris;
+        let mut scene = Scene::new(vec![mothership]);
+        let id = scene.particles()[0].uid;
+        scene.get_mut(id).unwrap().sprite = scene.sprites().id("mothership");
+        scene.set_boundaries(Boundaries {
+            default: BoundaryMode::Despawn,
+            ..Boundaries::default()
+        });
+
+        scene.clamp_into(20, 10);
+        let events = scene.step(20, 10, &[]);
+        assert!(events.is_empty(), "{:?}", events);
+        // The mothership reaches a cell either side of its anchor and one below it.
+        let cell = scene.get(id).unwrap().position.to_cell();
+        assert_eq!((cell.x, cell.y), (17, 7));
+    }
+
+    #[test]
+    fn particles_expire_at_the_end_of_their_lifetime() {
+        let mut scene = Scene::new(vec![rocket_at(5, 5).with_lifetime(3)]);
+        let id = scene.particles()[0].uid;
+        assert!(scene.step(20, 10, &[]).is_empty());
+        assert!(scene.step(20, 10, &[]).is_empty());
+        assert!(matches!(
+            scene.step(20, 10, &[]).as_slice(),
+            [GameEvent::Expired { id: expired }] if *expired == id
+        ));
+        assert!(scene.get(id).is_none());
+        assert!(scene.step(20, 10, &[]).is_empty());
+    }
+
+    #[test]
+    fn expiring_particles_do_not_collide() {
+        let mut fuel_cell = rocket_at(5, 5);
+        fuel_cell.kind = ParticleType::FuelCell;
+        let mut scene = Scene::new(vec![rocket_at(5, 5).with_lifetime(1), fuel_cell]);
+        let events = scene.step(20, 10, &[]);
+        assert!(matches!(events.as_slice(), [GameEvent::Expired { .. }]), "{:?}", events);
+        assert_eq!(scene.particles().len(), 1);
+        assert_eq!(scene.particles()[0].kind, ParticleType::FuelCell);
+    }
+
+    #[test]
+    fn crossing_rockets_explode_once() {
+        let mut left = rocket_at(5, 5);
+        left.velocity = Coordinate::new(16, 0);
+        let mut right = rocket_at(7, 5);
+        right.velocity = Coordinate::new(-16, 0);
+        let mut scene = Scene::new(vec![left, right]);
+
+        let mut impacts = 0;
+        let mut explosions = HashSet::new();
+        for _ in 0..20 {
+            let events = scene.step(20, 10, &[]);
+            impacts += events.iter().filter(|e| matches!(e, GameEvent::Impact { .. })).count();
+            // Explosions play out and disappear, so look for them every step.
+            explosions.extend(scene.particles().iter().filter(|p| p.kind == ParticleType::Effect).map(|p| p.uid));
+        }
+        assert!(impacts > 1, "only {} impacts", impacts);
+        assert_eq!(explosions.len(), 1);
+    }
+}
@@ -2,13 +2,13 @@
 // Subpixel scaling factor - positions and velocities are 16x more precise than terminal cells
 pub const SUBPIXEL_SCALE: i32 = 64;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Coordinate {
     pub x: i32,
     pub y: i32,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ConsoleCell {
     pub x: u16,
     pub y: u16,
@@ -20,12 +20,6 @@ impl ConsoleCell {
     }
 }
 
-impl PartialEq for ConsoleCell {
-    fn eq(&self, other: &Self) -> bool {
-        self.x == other.x && self.y == other.y
-    }
-}
-
 impl Default for Coordinate {
     fn default() -> Self {
         Self::new(0, 0)
@@ -43,7 +37,7 @@ impl Coordinate {
     }
 
     // Convert from subpixel coordinates to terminal cell coordinates
-    pub fn to_cell(&self) -> ConsoleCell {
+    pub fn to_cell(self) -> ConsoleCell {
         ConsoleCell::new(
             ((self.x as f32 / SUBPIXEL_SCALE as f32).round() as i32).max(0) as u16,
             ((self.y as f32 / SUBPIXEL_SCALE as f32).round() as i32).max(0) as u16,
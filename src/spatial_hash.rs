@@ -0,0 +1,42 @@
+// Broad-phase spatial hash: buckets items by the console cells they cover so that
+// finding the items near each other takes one pass instead of comparing every pair.
+use std::collections::HashMap;
+
+use crate::spatial::ConsoleCell;
+
+pub struct SpatialHash {
+    buckets: HashMap<ConsoleCell, Vec<usize>>,
+}
+
+impl SpatialHash {
+    // Bucket items by every cell they cover; item `i` is the i-th set of cells yielded.
+    pub fn build<I, C>(items: I) -> Self
+    where
+        I: IntoIterator<Item = C>,
+        C: IntoIterator<Item = ConsoleCell>,
+    {
+        let mut buckets: HashMap<ConsoleCell, Vec<usize>> = HashMap::new();
+        for (i, cells) in items.into_iter().enumerate() {
+            for cell in cells {
+                buckets.entry(cell).or_default().push(i);
+            }
+        }
+        Self { buckets }
+    }
+
+    // Every pair of items sharing at least one cell, once each, as (lower, higher) and
+    // in ascending order so results do not depend on the hash map's iteration order.
+    pub fn candidate_pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for bucket in self.buckets.values() {
+            for (n, &i) in bucket.iter().enumerate() {
+                for &j in bucket[n + 1..].iter() {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs.sort_unstable();
+        pairs.dedup();
+        pairs
+    }
+}
@@ -0,0 +1,172 @@
+// Particle spawning. All randomness comes from one seeded RNG so that a run can be
+// reproduced exactly from its seed.
+use crossterm::style::Color;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::particle::{Particle, ParticleType};
+use crate::spatial::{Coordinate, SUBPIXEL_SCALE};
+use crate::sprite::{Extent, SpriteRegistry};
+
+// A random interior cell of a playfield of the given size, in subpixel coordinates.
+fn random_interior_position<R: Rng>(rng: &mut R, cell_width: u16, cell_height: u16) -> Coordinate {
+    Coordinate::new(
+        ((rng.random::<u16>() % (cell_width - 2)) + 1) as i32 * SUBPIXEL_SCALE,
+        ((rng.random::<u16>() % (cell_height - 2)) + 1) as i32 * SUBPIXEL_SCALE,
+    )
+}
+
+// A drift speed of a quarter cell per frame, in a random diagonal direction.
+fn random_drift_component<R: Rng>(rng: &mut R) -> i32 {
+    let mut v = (rng.random::<i16>() % 2) as i32 * (SUBPIXEL_SCALE / 4);
+    if v == 0 {
+        v = SUBPIXEL_SCALE / 4;
+    }
+    v
+}
+
+// Mass of a rocket, the reference the other masses are chosen around.
+const ROCKET_MASS: u16 = 10;
+
+// What the starting scene holds besides the player's rocket and its fuel cell.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Population {
+    pub debris: usize,
+    pub depots: usize,
+    pub motherships: usize,
+}
+
+// A random position for a sprite reaching `extent` cells from its anchor, such that the
+// whole sprite lies in the interior where the playfield is big enough for it.
+fn random_position_for<R: Rng>(rng: &mut R, cell_width: u16, cell_height: u16, extent: Extent) -> Coordinate {
+    let (min_dx, min_dy, max_dx, max_dy) = extent;
+    let span_x = cell_width as i32 - 2 - (max_dx - min_dx);
+    let span_y = cell_height as i32 - 2 - (max_dy - min_dy);
+    if span_x < 1 || span_y < 1 {
+        return random_interior_position(rng, cell_width, cell_height);
+    }
+    Coordinate::new(
+        (rng.random_range(0..span_x) + 1 - min_dx) * SUBPIXEL_SCALE,
+        (rng.random_range(0..span_y) + 1 - min_dy) * SUBPIXEL_SCALE,
+    )
+}
+
+pub struct Spawner {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Spawner {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // A stationary rocket on a random interior cell.
+    pub fn spawn_rocket(&mut self, cell_width: u16, cell_height: u16) -> Particle {
+        let mut rocket = Particle::new(
+            Some(random_interior_position(&mut self.rng, cell_width, cell_height)),
+            None,
+            None,
+            ParticleType::Rocket,
+            Coordinate::new(200, 200),
+        );
+        rocket.set_color(Color::Red);
+        rocket.mass = Some(ROCKET_MASS);
+        rocket
+    }
+
+    // A fuel cell on a random interior cell, drifting diagonally.
+    pub fn spawn_fuel_cell(&mut self, cell_width: u16, cell_height: u16) -> Particle {
+        Particle::new(
+            Some(random_interior_position(&mut self.rng, cell_width, cell_height)),
+            Some(Coordinate::new(
+                random_drift_component(&mut self.rng),
+                random_drift_component(&mut self.rng),
+            )),
+            None,
+            ParticleType::FuelCell,
+            Coordinate::new(200, 200),
+        )
+    }
+
+    // A piece of debris on a random interior cell, drifting diagonally, with a random
+    // mass around the rocket's.
+    pub fn spawn_debris(&mut self, cell_width: u16, cell_height: u16) -> Particle {
+        let mut debris = Particle::new(
+            Some(random_interior_position(&mut self.rng, cell_width, cell_height)),
+            Some(Coordinate::new(
+                random_drift_component(&mut self.rng),
+                random_drift_component(&mut self.rng),
+            )),
+            None,
+            ParticleType::Debris,
+            Coordinate::new(200, 200),
+        );
+        debris.mass = Some(self.rng.random_range(ROCKET_MASS / 2..=ROCKET_MASS * 2));
+        debris
+    }
+
+    // A stationary fuel depot: a fuel cell with the `fuel_depot` sprite, if there is one.
+    pub fn spawn_fuel_depot(&mut self, cell_width: u16, cell_height: u16, sprites: &SpriteRegistry) -> Particle {
+        let sprite = sprites.id("fuel_depot");
+        let mut depot = Particle::new(
+            Some(random_position_for(&mut self.rng, cell_width, cell_height, sprites.extent(sprite))),
+            None,
+            None,
+            ParticleType::FuelCell,
+            Coordinate::new(200, 200),
+        );
+        depot.sprite = sprite;
+        depot
+    }
+
+    // A slow, heavy mothership: debris with the `mothership` sprite, if there is one.
+    pub fn spawn_mothership(&mut self, cell_width: u16, cell_height: u16, sprites: &SpriteRegistry) -> Particle {
+        let sprite = sprites.id("mothership");
+        let mut mothership = Particle::new(
+            Some(random_position_for(&mut self.rng, cell_width, cell_height, sprites.extent(sprite))),
+            Some(Coordinate::new(
+                random_drift_component(&mut self.rng) / 2,
+                random_drift_component(&mut self.rng) / 2,
+            )),
+            None,
+            ParticleType::Debris,
+            Coordinate::new(200, 200),
+        );
+        mothership.sprite = sprite;
+        mothership.mass = Some(ROCKET_MASS * 20);
+        mothership
+    }
+
+    // The starting scene: the player's rocket first, then a single drifting fuel cell,
+    // then the debris, fuel depots and motherships of `population`, in that order.
+    pub fn initial_particles(
+        &mut self,
+        cell_width: u16,
+        cell_height: u16,
+        population: &Population,
+        sprites: &SpriteRegistry,
+    ) -> Vec<Particle> {
+        let mut particles = vec![
+            self.spawn_rocket(cell_width, cell_height),
+            self.spawn_fuel_cell(cell_width, cell_height),
+        ];
+        for _ in 0..population.debris {
+            particles.push(self.spawn_debris(cell_width, cell_height));
+        }
+        for _ in 0..population.depots {
+            particles.push(self.spawn_fuel_depot(cell_width, cell_height, sprites));
+        }
+        for _ in 0..population.motherships {
+            particles.push(self.spawn_mothership(cell_width, cell_height, sprites));
+        }
+        particles
+    }
+}
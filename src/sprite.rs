@@ -0,0 +1,570 @@
+// Sprites: what particles look like on the map, and the cells they cover for collisions.
+// Definitions are loaded from TOML, see `assets/sprites.toml` for the format. A sprite
+// is either still, drawn from its own art, or an animation playing other sprites in turn.
+use crossterm::style::Color;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use crate::particle::{Particle, ParticleType};
+use crate::spatial::ConsoleCell;
+
+// The sprites compiled into the game, which a sprite file adds to or replaces.
+const BUILT_IN_SPRITES: &str = include_str!("../assets/sprites.toml");
+
+#[derive(Clone, Debug)]
+pub struct Sprite {
+    // Relative cells to the anchor along with the character and its foreground color;
+    // None for the particle's own foreground color.
+    pub cells: Vec<(ConsoleCell, char, Option<Color>)>,
+    // The anchor cell; must be one of the entries in `cells`.
+    pub anchor: ConsoleCell,
+}
+
+impl Sprite {
+    // The cells the sprite covers as (dx, dy) offsets from the particle's own cell.
+    pub fn footprint(&self) -> Vec<(i32, i32)> {
+        self.cells
+            .iter()
+            .map(|(cell, _, _)| {
+                (
+                    cell.x as i32 - self.anchor.x as i32,
+                    cell.y as i32 - self.anchor.y as i32,
+                )
+            })
+            .collect()
+    }
+}
+
+// Key of a sprite in the registry; cheap to copy onto every particle that uses it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SpriteId(u16);
+
+// How far a footprint reaches from the particle's cell: the smallest and largest
+// offsets, as (min_dx, min_dy, max_dx, max_dy).
+pub type Extent = (i32, i32, i32, i32);
+
+// The footprint of a particle without a sprite: its own cell.
+const SINGLE_CELL: [(i32, i32); 1] = [(0, 0)];
+
+// What happens once an animation has shown its last frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AnimationMode {
+    // Start over from the first frame.
+    #[default]
+    Loop,
+    // Stop: an animation played over a particle's sprite ends, and a particle's own
+    // sprite stays on its last frame.
+    Once,
+}
+
+#[derive(Clone, Debug)]
+struct Animation {
+    // Still sprites to show and for how many ticks each; None for a blank frame.
+    frames: Vec<(Option<SpriteId>, u32)>,
+    mode: AnimationMode,
+    // Whether the particle is despawned when a one-shot animation ends.
+    despawn: bool,
+    // Sum of the frame durations.
+    length: u32,
+}
+
+#[derive(Clone, Debug)]
+enum Look {
+    Still {
+        sprite: Sprite,
+        // One frame per compass point, in the order of `Particle::direction`.
+        directions: Option<Vec<Sprite>>,
+    },
+    Animated(Animation),
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    look: Look,
+    // Of the main frame, or of all frames of an animation together; the loader makes sure
+    // direction frames cover the same cells as the main one.
+    footprint: Vec<(i32, i32)>,
+    extent: Extent,
+}
+
+// Named sprite definitions, with their footprints worked out once up front, and the
+// sprite of each particle type.
+#[derive(Clone, Debug)]
+pub struct SpriteRegistry {
+    sprites: Vec<Entry>,
+    names: HashMap<String, SpriteId>,
+    by_type: HashMap<ParticleType, SpriteId>,
+}
+
+impl Default for SpriteRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            sprites: Vec::new(),
+            names: HashMap::new(),
+            by_type: HashMap::new(),
+        };
+        registry
+            .merge(BUILT_IN_SPRITES)
+            .expect("The built-in sprites are valid");
+        registry
+    }
+}
+
+// The file as written.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpriteFile {
+    #[serde(default)]
+    types: HashMap<ParticleType, String>,
+    #[serde(default)]
+    palette: HashMap<char, String>,
+    #[serde(default)]
+    sprites: BTreeMap<String, SpriteDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpriteDef {
+    art: Option<Vec<String>>,
+    colors: Option<Vec<String>>,
+    anchor: Option<Vec<String>>,
+    directions: Option<Directions>,
+    // An animation has frames instead of art.
+    frames: Option<Vec<AnimationFrameDef>>,
+    #[serde(default)]
+    mode: AnimationMode,
+    #[serde(default)]
+    despawn: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationFrameDef {
+    // A still sprite by name; blank when left out.
+    sprite: Option<String>,
+    ticks: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FrameDef {
+    art: Vec<String>,
+    colors: Option<Vec<String>>,
+    anchor: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Directions {
+    e: FrameDef,
+    se: FrameDef,
+    s: FrameDef,
+    sw: FrameDef,
+    w: FrameDef,
+    nw: FrameDef,
+    n: FrameDef,
+    ne: FrameDef,
+}
+
+impl SpriteRegistry {
+    // The built-in sprites, with those of the sprite file at `path` added over them.
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let mut registry = Self::default();
+        if let Some(path) = path {
+            let path = Path::new(path);
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            registry
+                .merge(&text)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(registry)
+    }
+
+    // Add the sprites of a sprite file, replacing any of the same name, and assign the
+    // particle types it lists their sprites.
+    fn merge(&mut self, text: &str) -> Result<(), String> {
+        let file: SpriteFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut palette = HashMap::new();
+        for (letter, name) in file.palette {
+            let color = Color::try_from(name.as_str())
+                .map_err(|_| format!("unknown color \"{}\"", name))?;
+            palette.insert(letter, color);
+        }
+
+        // Still sprites first, so that animations can refer to any of them by name.
+        let (animations, stills): (Vec<_>, Vec<_>) =
+            file.sprites.into_iter().partition(|(_, def)| def.frames.is_some());
+        for (name, def) in stills {
+            let error = |e: String| format!("sprite \"{}\": {}", name, e);
+            let art = def.art.ok_or_else(|| error(String::from("needs art or frames")))?;
+            let sprite = build_frame(&art, &def.colors, &def.anchor, &palette).map_err(error)?;
+            let directions = match def.directions {
+                Some(d) => {
+                    let mut footprint = sprite.footprint();
+                    footprint.sort();
+                    let points = [
+                        ("e", d.e),
+                        ("se", d.se),
+                        ("s", d.s),
+                        ("sw", d.sw),
+                        ("w", d.w),
+                        ("nw", d.nw),
+                        ("n", d.n),
+                        ("ne", d.ne),
+                    ];
+                    let mut frames = Vec::new();
+                    for (point, f) in points {
+                        let point_error = |e: String| error(format!("direction \"{}\": {}", point, e));
+                        let frame = build_frame(&f.art, &f.colors, &f.anchor, &palette).map_err(point_error)?;
+                        // Collisions and boundaries only know the main frame's cells.
+                        let mut cells = frame.footprint();
+                        cells.sort();
+                        if cells != footprint {
+                            return Err(point_error(String::from("covers other cells than the main frame")));
+                        }
+                        frames.push(frame);
+                    }
+                    Some(frames)
+                }
+                None => None,
+            };
+            self.register(&name, Look::Still { sprite, directions });
+        }
+        for (name, def) in animations {
+            let error = |e: String| format!("sprite \"{}\": {}", name, e);
+            if def.art.is_some() || def.colors.is_some() || def.anchor.is_some() || def.directions.is_some() {
+                return Err(error(String::from("an animation cannot also have art")));
+            }
+            let animation = self
+                .build_animation(def.frames.unwrap_or_default(), def.mode, def.despawn)
+                .map_err(error)?;
+            self.register(&name, Look::Animated(animation));
+        }
+
+        for (kind, name) in file.types {
+            let id = self
+                .id(&name)
+                .ok_or_else(|| format!("{:?} uses unknown sprite \"{}\"", kind, name))?;
+            self.by_type.insert(kind, id);
+        }
+
+        // Animations registered earlier may show frames that were just replaced, and so
+        // cover other cells now.
+        for i in 0..self.sprites.len() {
+            let Look::Animated(animation) = &self.sprites[i].look else {
+                continue;
+            };
+            // Nor may those frames have been replaced by animations.
+            let animated = animation.frames.iter().filter_map(|(frame, _)| *frame).find(|frame| {
+                matches!(self.sprites[frame.0 as usize].look, Look::Animated(_))
+            });
+            if let Some(frame) = animated {
+                let name_of = |id: SpriteId| {
+                    self.names.iter().find(|(_, other)| **other == id).map_or("", |(name, _)| name.as_str())
+                };
+                return Err(format!(
+                    "sprite \"{}\": \"{}\" is an animation itself",
+                    name_of(SpriteId(i as u16)),
+                    name_of(frame)
+                ));
+            }
+            let footprint = self.footprint_of(&self.sprites[i].look);
+            self.sprites[i].extent = extent_of(&footprint);
+            self.sprites[i].footprint = footprint;
+        }
+        Ok(())
+    }
+
+    // Frames of an animation, each naming a still sprite that is already registered.
+    fn build_animation(
+        &self,
+        frames: Vec<AnimationFrameDef>,
+        mode: AnimationMode,
+        despawn: bool,
+    ) -> Result<Animation, String> {
+        let mut resolved = Vec::new();
+        for frame in frames {
+            if frame.ticks == 0 {
+                return Err(String::from("frames must last at least one tick"));
+            }
+            let id = match frame.sprite {
+                Some(name) => {
+                    let id = self
+                        .id(&name)
+                        .ok_or_else(|| format!("unknown sprite \"{}\"", name))?;
+                    if let Look::Animated(_) = self.sprites[id.0 as usize].look {
+                        return Err(format!("\"{}\" is an animation itself", name));
+                    }
+                    Some(id)
+                }
+                None => None,
+            };
+            resolved.push((id, frame.ticks));
+        }
+        if resolved.iter().all(|(id, _)| id.is_none()) {
+            return Err(String::from("an animation needs at least one frame with a sprite"));
+        }
+        if despawn && mode != AnimationMode::Once {
+            return Err(String::from("only animations played once can despawn"));
+        }
+        Ok(Animation {
+            length: resolved.iter().map(|(_, ticks)| ticks).sum(),
+            frames: resolved,
+            mode,
+            despawn,
+        })
+    }
+
+    // Add a sprite under `name`, replacing any sprite already registered under it.
+    fn register(&mut self, name: &str, look: Look) -> SpriteId {
+        let footprint = self.footprint_of(&look);
+        let extent = extent_of(&footprint);
+        let entry = Entry {
+            look,
+            footprint,
+            extent,
+        };
+        if let Some(&id) = self.names.get(name) {
+            self.sprites[id.0 as usize] = entry;
+            return id;
+        }
+        let id = SpriteId(self.sprites.len() as u16);
+        self.sprites.push(entry);
+        self.names.insert(name.to_string(), id);
+        id
+    }
+
+    // The cells of a still sprite's main frame, or of all frames of an animation together.
+    fn footprint_of(&self, look: &Look) -> Vec<(i32, i32)> {
+        match look {
+            Look::Still { sprite, .. } => sprite.footprint(),
+            Look::Animated(animation) => {
+                let mut cells: Vec<(i32, i32)> = animation
+                    .frames
+                    .iter()
+                    .filter_map(|(id, _)| *id)
+                    .flat_map(|id| self.footprint(Some(id)).iter().copied())
+                    .collect();
+                cells.sort();
+                cells.dedup();
+                cells
+            }
+        }
+    }
+
+    pub fn id(&self, name: &str) -> Option<SpriteId> {
+        self.names.get(name).copied()
+    }
+
+    // The sprite a particle is drawn with: its own, or else its type's.
+    pub fn id_for(&self, particle: &Particle) -> Option<SpriteId> {
+        particle.sprite.or_else(|| self.by_type.get(&particle.kind).copied())
+    }
+
+    // The frame to show `ticks` into the sprite's animation, if it has one, for a
+    // direction (see `Particle::direction`); the main frame when there is no direction or
+    // the sprite has no direction frames. None for a blank animation frame.
+    pub fn frame(&self, id: SpriteId, direction: Option<u8>, ticks: u32) -> Option<&Sprite> {
+        match &self.sprites[id.0 as usize].look {
+            Look::Still { sprite, directions } => match (directions, direction) {
+                (Some(frames), Some(d)) => Some(&frames[d as usize % frames.len()]),
+                _ => Some(sprite),
+            },
+            Look::Animated(animation) => {
+                let mut t = match animation.mode {
+                    AnimationMode::Loop => ticks % animation.length,
+                    AnimationMode::Once => ticks.min(animation.length - 1),
+                };
+                for &(frame, duration) in animation.frames.iter() {
+                    if t < duration {
+                        return frame.and_then(|frame| self.frame(frame, direction, 0));
+                    }
+                    t -= duration;
+                }
+                None
+            }
+        }
+    }
+
+    // For a one-shot animation that has played out after `ticks`: whether the particle
+    // playing it is to be despawned. None while it plays and for anything else.
+    pub fn finished(&self, id: SpriteId, ticks: u32) -> Option<bool> {
+        match &self.sprites[id.0 as usize].look {
+            Look::Animated(animation) if animation.mode == AnimationMode::Once && ticks >= animation.length => {
+                Some(animation.despawn)
+            }
+            _ => None,
+        }
+    }
+
+    // The footprint of a particle with the given sprite, see `Sprite::footprint`.
+    pub fn footprint(&self, id: Option<SpriteId>) -> &[(i32, i32)] {
+        match id {
+            Some(id) => &self.sprites[id.0 as usize].footprint,
+            None => &SINGLE_CELL,
+        }
+    }
+
+    pub fn extent(&self, id: Option<SpriteId>) -> Extent {
+        match id {
+            Some(id) => self.sprites[id.0 as usize].extent,
+            None => (0, 0, 0, 0),
+        }
+    }
+}
+
+// Only what the simulation sees: footprints, animation timing and the sprite of each
+// particle type. Art and colors are left out.
+impl Hash for SpriteRegistry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for entry in self.sprites.iter() {
+            entry.footprint.hash(state);
+            if let Look::Animated(animation) = &entry.look {
+                animation.frames.hash(state);
+                animation.mode.hash(state);
+                animation.despawn.hash(state);
+            }
+        }
+        let mut by_type: Vec<_> = self.by_type.iter().collect();
+        by_type.sort_by_key(|(kind, _)| **kind);
+        by_type.hash(state);
+    }
+}
+
+fn extent_of(footprint: &[(i32, i32)]) -> Extent {
+    footprint.iter().fold((0, 0, 0, 0), |(x0, y0, x1, y1), &(dx, dy)| {
+        (x0.min(dx), y0.min(dy), x1.max(dx), y1.max(dy))
+    })
+}
+
+// Build one frame from its art and optional color and anchor layers.
+fn build_frame(
+    art: &[String],
+    colors: &Option<Vec<String>>,
+    anchor: &Option<Vec<String>>,
+    palette: &HashMap<char, Color>,
+) -> Result<Sprite, String> {
+    let layer_char = |layer: &Option<Vec<String>>, x: usize, y: usize| {
+        layer
+            .as_ref()
+            .and_then(|rows| rows.get(y))
+            .and_then(|row| row.chars().nth(x))
+            .unwrap_or(' ')
+    };
+
+    let mut cells = Vec::new();
+    let mut anchor_cell = None;
+    for (y, row) in art.iter().enumerate() {
+        for (x, ch) in row.chars().enumerate() {
+            let cell = ConsoleCell::new(x as u16, y as u16);
+            let marked = layer_char(anchor, x, y) == '@';
+            if ch == ' ' {
+                if marked {
+                    return Err(String::from("the anchor is on an empty cell"));
+                }
+                continue;
+            }
+            if marked {
+                if anchor_cell.is_some() {
+                    return Err(String::from("more than one anchor"));
+                }
+                anchor_cell = Some(cell);
+            }
+            let color = match layer_char(colors, x, y) {
+                ' ' | '.' => None,
+                letter => Some(
+                    *palette
+                        .get(&letter)
+                        .ok_or_else(|| format!("'{}' is not in the palette", letter))?,
+                ),
+            };
+            cells.push((cell, ch, color));
+        }
+    }
+
+    let Some(&(first, _, _)) = cells.first() else {
+        return Err(String::from("the art is empty"));
+    };
+    if anchor.is_some() && anchor_cell.is_none() {
+        return Err(String::from("the anchor layer has no '@' on the art"));
+    }
+    Ok(Sprite {
+        cells,
+        anchor: anchor_cell.unwrap_or(first),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The error merging `text` over the built-in sprites gives.
+    fn merge_error(text: &str) -> String {
+        SpriteRegistry::default().merge(text).unwrap_err()
+    }
+
+    #[test]
+    fn anchors_must_be_on_the_art_and_unique() {
+        let error = merge_error("[sprites.ship]\nart = [\"ab\"]\nanchor = [\"@@\"]\n");
+        assert_eq!(error, "sprite \"ship\": more than one anchor");
+        let error = merge_error("[sprites.ship]\nart = [\"a \"]\nanchor = [\" @\"]\n");
+        assert_eq!(error, "sprite \"ship\": the anchor is on an empty cell");
+        let error = merge_error("[sprites.ship]\nart = [\"ab\"]\nanchor = [\"..\"]\n");
+        assert_eq!(error, "sprite \"ship\": the anchor layer has no '@' on the art");
+    }
+
+    #[test]
+    fn colors_must_come_from_the_palette() {
+        let error = merge_error("[sprites.ship]\nart = [\"ab\"]\ncolors = [\".z\"]\n");
+        assert_eq!(error, "sprite \"ship\": 'z' is not in the palette");
+        let error = merge_error("[palette]\nz = \"mauve\"\n");
+        assert_eq!(error, "unknown color \"mauve\"");
+    }
+
+    #[test]
+    fn animations_cannot_be_frames_of_animations() {
+        let error = merge_error("[sprites.blink]\nframes = [{ sprite = \"fuel_cell\", ticks = 2 }]\n");
+        assert_eq!(error, "sprite \"blink\": \"fuel_cell\" is an animation itself");
+    }
+
+    #[test]
+    fn replacing_a_frame_updates_the_animations_showing_it() {
+        let mut registry = SpriteRegistry::default();
+        registry
+            .merge("[sprites.fuel_cell_bright]\nart = [\"FFF\", \"FFF\"]\n")
+            .unwrap();
+        let fuel_cell = registry.id("fuel_cell");
+        assert_eq!(registry.extent(fuel_cell), (0, 0, 2, 1));
+        assert_eq!(registry.footprint(fuel_cell).len(), 6);
+
+        // A frame cannot become an animation either.
+        let error = merge_error("[sprites.fuel_cell_dim]\nframes = [{ sprite = \"debris\", ticks = 2 }]\n");
+        assert_eq!(error, "sprite \"fuel_cell\": \"fuel_cell_dim\" is an animation itself");
+    }
+
+    #[test]
+    fn direction_frames_must_cover_the_main_frames_cells() {
+        let points = ["e", "se", "s", "sw", "w", "nw", "n", "ne"];
+        let directions = |n_art: &str| {
+            points
+                .iter()
+                .map(|p| {
+                    let art = if *p == "n" { n_art } else { "\"x\"" };
+                    format!("[sprites.ship.directions.{}]\nart = [{}]\n", p, art)
+                })
+                .collect::<String>()
+        };
+        let ship = "[sprites.ship]\nart = [\"x\"]\n";
+
+        let mut registry = SpriteRegistry::default();
+        registry.merge(&format!("{}{}", ship, directions("\"^\""))).unwrap();
+        assert_eq!(registry.footprint(registry.id("ship")), &[(0, 0)]);
+
+        let error = merge_error(&format!("{}{}", ship, directions("\"^\", \"|\"")));
+        assert_eq!(error, "sprite \"ship\": direction \"n\": covers other cells than the main frame");
+    }
+}
@@ -0,0 +1,98 @@
+// Terminal session guard: puts the terminal into game mode and always puts it back.
+use crossterm::cursor::Show;
+use crossterm::event::{
+    KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::execute;
+use crossterm::style::{Colors, ResetColor, SetColors};
+use crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+    supports_keyboard_enhancement,
+};
+use std::io::{self, stdout};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::console::{DEFAULT_BACKGROUND_COLOR, DEFAULT_FOREGROUND_COLOR};
+
+// Whether the terminal is currently in game mode; makes restoring idempotent so the
+// panic hook and the guard's drop can both run without undoing things twice.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// Whether keyboard enhancement flags were pushed and so need popping.
+static ENHANCED: AtomicBool = AtomicBool::new(false);
+static PANIC_HOOK: Once = Once::new();
+
+// While alive, the terminal is in the alternate screen with raw mode on and, where the
+// terminal supports them, the keyboard enhancement flags pushed. Dropping it (or
+// panicking) restores the shell.
+pub struct TerminalSession {
+    keyboard_enhancement: bool,
+}
+
+impl TerminalSession {
+    pub fn enter() -> io::Result<Self> {
+        install_panic_hook();
+
+        enable_raw_mode()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        // From here on a failure still restores whatever was set up, via drop.
+        let mut session = Self {
+            keyboard_enhancement: false,
+        };
+        execute!(
+            stdout(),
+            EnterAlternateScreen,
+            SetColors(Colors::new(DEFAULT_FOREGROUND_COLOR, DEFAULT_BACKGROUND_COLOR))
+        )?;
+
+        // A terminal that cannot answer the query is treated as not supporting it.
+        if supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )?;
+            ENHANCED.store(true, Ordering::SeqCst);
+            session.keyboard_enhancement = true;
+        }
+        Ok(session)
+    }
+
+    // Whether the terminal reports key release events.
+    pub fn keyboard_enhancement(&self) -> bool {
+        self.keyboard_enhancement
+    }
+}
+
+impl Drop for TerminalSession {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+// Undo everything `enter` did. Errors are ignored: there is nothing better to do
+// with them while tearing down, and the remaining steps should still run.
+fn restore() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    if ENHANCED.swap(false, Ordering::SeqCst) {
+        let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+    }
+    let _ = execute!(stdout(), ResetColor, Show, LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+}
+
+// Restore the terminal before the default hook prints, so the panic message lands
+// on the normal screen in cooked mode instead of being lost with the alternate one.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            previous(info);
+        }));
+    });
+}